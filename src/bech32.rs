@@ -0,0 +1,197 @@
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+const BECH32M_CONST: u32 = 0x2bc830a3;
+const CHECKSUM_LEN: usize = 6;
+/// Longest string BIP-350 allows.
+const MAX_LENGTH: usize = 90;
+
+/// Encodes `data` as a bech32m string (BIP-350) with the human readable prefix `hrp`.
+pub fn encode(hrp: &str, data: &[u8]) -> String {
+    let hrp = hrp.to_lowercase();
+    let values = convert_bits(data, 8, 5, true).unwrap();
+    let checksum = create_checksum(&hrp, &values);
+
+    let mut encoded = hrp;
+    encoded.push('1');
+    for value in values.iter().chain(checksum.iter()) {
+        encoded.push(CHARSET[*value as usize] as char);
+    }
+    encoded
+}
+
+/// Decodes a bech32m string into its human readable part and data bytes.
+pub fn decode(string: &str) -> Result<(String, Vec<u8>), String> {
+    let (hrp, values) = decode_values(string)?;
+    let bytes = convert_bits(&values, 5, 8, false).ok_or_else(|| "Invalid padding".to_string())?;
+    Ok((hrp, bytes))
+}
+
+/// Checks the checksum of a bech32m string and returns its human readable part and the 5-bit
+/// values of its data part.
+fn decode_values(string: &str) -> Result<(String, Vec<u8>), String> {
+    if string.len() > MAX_LENGTH {
+        return Err("Too long".to_string());
+    }
+    if string.chars().any(|c| c.is_lowercase()) && string.chars().any(|c| c.is_uppercase()) {
+        return Err("Mixed case".to_string());
+    }
+    let string = string.to_lowercase();
+    let separator = string
+        .rfind('1')
+        .ok_or_else(|| "Missing separator".to_string())?;
+    let (hrp, data) = (&string[..separator], &string[separator + 1..]);
+    if hrp.is_empty() || data.len() < CHECKSUM_LEN {
+        return Err("Too short".to_string());
+    }
+    if hrp.chars().any(|c| !('!'..='~').contains(&c)) {
+        return Err("Invalid prefix".to_string());
+    }
+
+    let mut values = data
+        .chars()
+        .map(|c| {
+            CHARSET
+                .iter()
+                .position(|x| *x as char == c)
+                .map(|value| value as u8)
+                .ok_or_else(|| format!("Invalid character '{}'", c))
+        })
+        .collect::<Result<Vec<u8>, String>>()?;
+
+    let mut checked = hrp_expand(hrp);
+    checked.extend_from_slice(&values);
+    if polymod(&checked) != BECH32M_CONST {
+        return Err("Invalid checksum".to_string());
+    }
+
+    values.truncate(values.len() - CHECKSUM_LEN);
+    Ok((hrp.to_string(), values))
+}
+
+fn create_checksum(hrp: &str, values: &[u8]) -> Vec<u8> {
+    let mut data = hrp_expand(hrp);
+    data.extend_from_slice(values);
+    data.extend_from_slice(&[0u8; CHECKSUM_LEN]);
+    let checksum = polymod(&data) ^ BECH32M_CONST;
+    (0..CHECKSUM_LEN)
+        .map(|i| ((checksum >> (5 * (5 - i))) & 31) as u8)
+        .collect()
+}
+
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut expanded: Vec<u8> = hrp.bytes().map(|b| b >> 5).collect();
+    expanded.push(0);
+    expanded.extend(hrp.bytes().map(|b| b & 31));
+    expanded
+}
+
+fn polymod(values: &[u8]) -> u32 {
+    let mut checksum = 1u32;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = ((checksum & 0x1ffffff) << 5) ^ *value as u32;
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+/// Regroups `data` from `from`-bit to `to`-bit values.
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Option<Vec<u8>> {
+    let mut accumulator = 0u32;
+    let mut bits = 0u32;
+    let max = (1u32 << to) - 1;
+    let mut converted = Vec::new();
+    for value in data {
+        if (*value as u32) >> from != 0 {
+            return None;
+        }
+        accumulator = (accumulator << from) | *value as u32;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            converted.push(((accumulator >> bits) & max) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            converted.push(((accumulator << (to - bits)) & max) as u8);
+        }
+    } else if bits >= from || ((accumulator << (to - bits)) & max) != 0 {
+        return None;
+    }
+    Some(converted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test vectors of BIP-350.
+    const VALID: &[&str] = &[
+        "A1LQFN3A",
+        "a1lqfn3a",
+        "an83characterlonghumanreadablepartthatcontainsthetheexcludedcharactersbioandnumber11sg7hg6",
+        "abcdef1l7aum6echk45nj3s0wdvt2fg8x9yrzpqzd3ryx",
+        "11llllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllludsr8",
+        "split1checkupstagehandshakeupstreamerranterredcaperredlc445v",
+        "?1v759aa",
+    ];
+
+    const INVALID: &[&str] = &[
+        "\x201xj0phk",
+        "\x7f1g6xzxy",
+        "\u{80}1vctc34",
+        "an84characterslonghumanreadablepartthatcontainsthetheexcludedcharactersbioandnumber11d6pts4",
+        "qyrz8wqd2c9m",
+        "1qyrz8wqd2c9m",
+        "y1b0jsk6g",
+        "lt1igcx5c0",
+        "in1muywd",
+        "mm1crxm3i",
+        "au1s5cgom",
+        "M1VUXWEZ",
+        "16plkw9",
+        "1p2gdwpf",
+        // Valid bech32, which uses a different checksum constant.
+        "A12UEL5L",
+    ];
+
+    #[test]
+    fn valid_vectors() {
+        for string in VALID {
+            let (hrp, _) =
+                decode_values(string).unwrap_or_else(|err| panic!("{}: {}", string, err));
+            assert_eq!(hrp, string[..string.rfind('1').unwrap()].to_lowercase());
+        }
+    }
+
+    #[test]
+    fn invalid_vectors() {
+        for string in INVALID {
+            assert!(
+                decode_values(string).is_err(),
+                "{:?} should be invalid",
+                string
+            );
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = (0..16).collect::<Vec<u8>>();
+        let encoded = encode("zen", &data);
+        assert_eq!(decode(&encoded), Ok(("zen".to_string(), data)));
+        assert_eq!(decode(&encoded.to_uppercase()), decode(&encoded));
+    }
+
+    #[test]
+    fn detects_a_changed_character() {
+        let mut encoded = encode("zen", &[7u8; 16]).into_bytes();
+        encoded[10] = if encoded[10] == b'q' { b'p' } else { b'q' };
+        assert!(decode(&String::from_utf8(encoded).unwrap()).is_err());
+    }
+}
@@ -1,6 +1,30 @@
+use std::{
+    fs,
+    path::PathBuf,
+    process, thread,
+    time::{Duration, Instant},
+};
+
 use clap::{Parser, Subcommand};
+use openssl::{
+    hash::{hash, MessageDigest},
+    pkey::PKey,
+};
 
-use zenchain::{client::BlockchainClient, keys, server, transaction::Transaction};
+use zenchain::{
+    block,
+    blockchain::{AccountState, TransactionStatus},
+    client::BlockchainClient,
+    config::{self, Config},
+    error::{ConsensusError, Error, Result, ValidationError},
+    keys::{self, KeyType},
+    mempool,
+    multisig::MultisigPolicy,
+    script::{self, Script},
+    server,
+    transaction::{Authorization, Output, Payment, Transaction},
+    types::{Address, BlockId, ClientNetworkMessage, PublicKey, ServerNetworkMessage},
+};
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
@@ -11,6 +35,14 @@ struct Cli {
 
     #[clap(short, long, value_parser)]
     node: Option<String>,
+
+    /// Directory holding the keys and nodes.txt. Defaults to the current directory.
+    #[clap(long, value_parser)]
+    data_dir: Option<PathBuf>,
+
+    /// Config file to use instead of zenchain.toml in the data directory.
+    #[clap(short, long, value_parser)]
+    config: Option<PathBuf>,
 }
 
 #[derive(Subcommand)]
@@ -20,12 +52,230 @@ enum Commands {
         keys: KeyCommands,
     },
     GetAddress,
-    Balance,
+    Balance {
+        /// Check the balance against the block headers instead of trusting the node
+        #[clap(long, value_parser)]
+        verify: bool,
+    },
+    Supply,
     Send {
+        #[clap(short, long, value_parser, required_unless_present = "batch")]
+        to: Option<String>,
+        /// Amount in ZEN, e.g. 12.5
+        #[clap(short, long, value_parser = keys::parse_amount, required_unless_present = "batch")]
+        amount: Option<u128>,
+        /// Pay every `address,amount` line of a file in one transaction
+        #[clap(short, long, value_parser, conflicts_with_all = &["to", "amount"])]
+        batch: Option<PathBuf>,
+        /// Fee in ZEN paid to the miner
+        #[clap(long, value_parser = keys::parse_amount, default_value = "0")]
+        fee: u128,
+        /// Last block height the transaction can be mined at
+        #[clap(long, value_parser)]
+        valid_until: Option<u128>,
+        /// Reference for the recipient, at most 256 bytes
+        #[clap(short, long, value_parser)]
+        memo: Option<String>,
+        /// Return only once the transaction is this many blocks deep. Fails if it drops out of
+        /// the chain.
+        #[clap(short, long, value_parser)]
+        wait_confirmations: Option<u128>,
+        /// Seconds to wait for the confirmations
+        #[clap(
+            long,
+            value_parser,
+            default_value_t = 600,
+            requires = "wait-confirmations"
+        )]
+        timeout: u64,
+    },
+    /// Replace a pending transaction of the default key with one paying a higher fee
+    BumpFee {
+        /// New fee in ZEN. Must be higher than the pending one.
+        #[clap(long, value_parser = keys::parse_amount)]
+        fee: u128,
+        /// Index of the pending transaction. Defaults to the oldest one.
+        #[clap(short, long, value_parser)]
+        index: Option<u128>,
+    },
+    /// List the confirmed transactions of an address
+    History {
+        /// Defaults to the address of the default key
+        #[clap(value_parser)]
+        address: Option<String>,
+    },
+    Miner {
+        #[clap(subcommand)]
+        miner: MinerCommands,
+    },
+    /// Create, sign and broadcast transaction files, e.g. to sign on an offline machine
+    Tx {
+        #[clap(subcommand)]
+        tx: TxCommands,
+    },
+    /// Create and show M-of-N multisig account policies
+    Multisig {
+        #[clap(subcommand)]
+        multisig: MultisigCommands,
+    },
+    /// Inspect spending condition scripts
+    Script {
+        #[clap(subcommand)]
+        script: ScriptCommands,
+    },
+    Chain {
+        #[clap(subcommand)]
+        chain: ChainCommands,
+    },
+    /// Show a block with its transactions
+    Block {
+        /// Block hash, or height on the main chain
+        #[clap(value_parser)]
+        id: String,
+    },
+    /// Show the balance and recent transactions of any address
+    Address {
+        #[clap(value_parser)]
+        address: String,
+        /// How many confirmed transactions to show
+        #[clap(long, value_parser, default_value = "10")]
+        recent: u32,
+    },
+}
+
+#[derive(Subcommand)]
+enum ChainCommands {
+    /// Show the height, tip and supply of the main chain
+    Info,
+}
+
+#[derive(Subcommand)]
+enum ScriptCommands {
+    /// Print the address of the account locked by a script file
+    Address {
+        #[clap(value_parser)]
+        file: PathBuf,
+    },
+    /// Print the sha3 hash of hex data, e.g. to build a hashlock
+    Hash {
+        #[clap(value_parser)]
+        data: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum MultisigCommands {
+    /// Write a policy file for the account controlled by `threshold` of the given keys
+    Create {
+        #[clap(short, long, value_parser)]
+        threshold: u8,
+        /// Key name or path to a PEM public key file. Repeat once per key, order matters.
+        #[clap(short, long = "key", value_parser, required = true)]
+        keys: Vec<String>,
+        #[clap(short, long, value_parser)]
+        out: PathBuf,
+    },
+    /// Print the address and keys of a policy file
+    Info {
+        #[clap(value_parser)]
+        file: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum TxCommands {
+    /// Write an unsigned transaction to a file
+    Create {
+        #[clap(short, long, value_parser, required_unless_present = "batch")]
+        to: Option<String>,
+        /// Amount in ZEN, e.g. 12.5
+        #[clap(short, long, value_parser = keys::parse_amount, required_unless_present = "batch")]
+        amount: Option<u128>,
+        /// Pay every `address,amount` line of a file in one transaction
+        #[clap(short, long, value_parser, conflicts_with_all = &["to", "amount"])]
+        batch: Option<PathBuf>,
+        /// Fee in ZEN paid to the miner
+        #[clap(long, value_parser = keys::parse_amount, default_value = "0")]
+        fee: u128,
+        /// Sending address. Defaults to the address of the default key.
+        #[clap(short, long, value_parser, conflicts_with = "policy")]
+        from: Option<String>,
+        /// Multisig policy file of the sending account
+        #[clap(short, long, value_parser, conflicts_with = "script")]
+        policy: Option<PathBuf>,
+        /// Script file of the sending account
+        #[clap(short, long, value_parser, conflicts_with = "from")]
+        script: Option<PathBuf>,
+        /// Transaction index. Defaults to the one after the sender's pending transactions.
         #[clap(short, long, value_parser)]
-        to: String,
+        index: Option<u128>,
+        /// Block height before which the transaction can't be mined
+        #[clap(long, value_parser)]
+        not_before: Option<u128>,
+        /// Last block height the transaction can be mined at
+        #[clap(long, value_parser)]
+        valid_until: Option<u128>,
+        /// Reference for the recipient, at most 256 bytes
+        #[clap(short, long, value_parser)]
+        memo: Option<String>,
+        #[clap(short, long, value_parser)]
+        out: PathBuf,
+    },
+    /// Sign a transaction file in place. Needs no network access. For multisig transactions
+    /// this adds one partial signature.
+    Sign {
+        #[clap(value_parser)]
+        file: PathBuf,
+        #[clap(short, long, value_parser)]
+        key: Option<String>,
+    },
+    /// Push a data item, e.g. a hash preimage, onto the witness of a script transaction
+    Witness {
+        #[clap(value_parser)]
+        file: PathBuf,
+        /// 0x prefixed hex
+        #[clap(value_parser)]
+        data: String,
+    },
+    /// Merge the partial signatures of copies of a multisig transaction
+    Combine {
+        #[clap(value_parser, required = true)]
+        files: Vec<PathBuf>,
         #[clap(short, long, value_parser)]
-        amount: u128,
+        out: PathBuf,
+    },
+    /// Print the contents of a transaction file
+    Inspect {
+        #[clap(value_parser)]
+        file: PathBuf,
+    },
+    /// Submit a signed transaction file to all nodes
+    Broadcast {
+        #[clap(value_parser)]
+        file: PathBuf,
+    },
+    /// Show whether a transaction is pending or mined, and how deep
+    Status {
+        /// Transaction id as printed by send
+        #[clap(value_parser)]
+        id: String,
+    },
+    /// Check that a transaction is mined, using only block headers and a Merkle proof from the
+    /// node
+    Verify {
+        /// Transaction id as printed by send
+        #[clap(value_parser)]
+        id: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum MinerCommands {
+    Start,
+    Stop,
+    Payout {
+        #[clap(value_parser)]
+        address: String,
     },
 }
 
@@ -34,6 +284,9 @@ enum KeyCommands {
     Generate {
         #[clap(value_parser)]
         name: String,
+        /// One of rsa2048, ed25519 or secp256k1
+        #[clap(short = 't', long = "type", value_parser, default_value = "rsa2048")]
+        key_type: KeyType,
     },
     Delete {
         #[clap(value_parser)]
@@ -44,11 +297,51 @@ enum KeyCommands {
         name: String,
     },
     List,
+    ChangePassphrase {
+        #[clap(value_parser)]
+        name: String,
+    },
+    /// Encrypt plaintext keys. Migrates all plaintext keys if no name is given.
+    Encrypt {
+        #[clap(value_parser)]
+        name: Option<String>,
+    },
+    /// Create a wallet seed phrase and derive its first key
+    Seed {
+        #[clap(long, value_parser, default_value = "seed")]
+        prefix: String,
+    },
+    /// Derive another key from the wallet seed
+    Derive {
+        #[clap(value_parser)]
+        name: String,
+        #[clap(short, long, value_parser)]
+        index: u32,
+    },
+    /// Restore a wallet seed phrase and regenerate its keys
+    Restore {
+        #[clap(long, value_parser, default_value = "seed")]
+        prefix: String,
+        /// Number of keys to regenerate
+        #[clap(short, long, value_parser, default_value_t = 1)]
+        count: u32,
+    },
 }
 
 fn main() {
     let cli = Cli::parse();
-    let client = BlockchainClient::new(&cli.node.unwrap_or("localhost:8888".to_string()));
+    if let Err(err) = run(cli) {
+        println!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> Result<()> {
+    config::init(Config::load(
+        cli.config.as_deref(),
+        cli.data_dir.as_deref(),
+    )?);
+    let client = BlockchainClient::new(&cli.node.unwrap_or(config::get().node.clone()));
 
     let all_clients = server::load_nodes()
         .iter()
@@ -59,36 +352,629 @@ fn main() {
     // matches just as you would the top level cmd
     match &cli.command {
         Commands::Keys { keys } => match keys {
-            KeyCommands::Generate { name } => keys::generate_keypair(name.to_string()),
-            KeyCommands::List => keys::list_keypairs(),
-            KeyCommands::Delete { name } => keys::delete_key(name.to_string()),
-            KeyCommands::SetDefault { name } => keys::set_default_keypair(name.to_string()),
+            KeyCommands::Generate { name, key_type } => {
+                keys::generate_keypair(name.to_string(), *key_type)?
+            }
+            KeyCommands::List => keys::list_keypairs()?,
+            KeyCommands::Delete { name } => keys::delete_key(name.to_string())?,
+            KeyCommands::SetDefault { name } => keys::set_default_keypair(name.to_string())?,
+            KeyCommands::ChangePassphrase { name } => keys::change_passphrase(name.to_string())?,
+            KeyCommands::Encrypt { name } => keys::encrypt_keys(name.clone())?,
+            KeyCommands::Seed { prefix } => keys::generate_seed(prefix.to_string())?,
+            KeyCommands::Derive { name, index } => keys::derive_keypair(name.to_string(), *index)?,
+            KeyCommands::Restore { prefix, count } => {
+                keys::restore_seed(prefix.to_string(), *count)?
+            }
         },
         Commands::GetAddress => {
             println!(
                 "Your address is: \n{}",
-                keys::format_address(&keys::keypair_to_address(&keys::load_keypair(None)))
+                keys::format_address(&keys::keypair_to_address(&keys::load_public_key(None)?)?)
+            );
+        }
+        Commands::Send {
+            to,
+            amount,
+            batch,
+            fee,
+            valid_until,
+            memo,
+            wait_confirmations,
+            timeout,
+        } => {
+            let payment = payment(to, amount, batch)?;
+            let key = keys::load_keypair(None)?;
+            let sender = keys::keypair_to_address(&key)?;
+            let index = next_index(&client, sender)?;
+            let mut transaction = Transaction::with_payment(sender, payment, index);
+            transaction.fee = *fee;
+            transaction.valid_until = *valid_until;
+            transaction.memo = memo.as_ref().map(|memo| memo.as_bytes().to_vec());
+            transaction.check_memo()?;
+            transaction.sign(&key)?;
+            let accepted = broadcast(&transaction, &all_clients);
+            println!("Sent {}", transaction);
+            println!("Transaction id: {}", keys::format_hash(&transaction.id()));
+            if let Some(confirmations) = wait_confirmations {
+                // Only a node that has the transaction can report it as pending.
+                let node = accepted
+                    .iter()
+                    .find(|node| node.address == client.address)
+                    .or(accepted.first())
+                    .ok_or_else(|| {
+                        Error::Network("No node accepted the transaction".to_string())
+                    })?;
+                wait_for_confirmations(
+                    node,
+                    &transaction,
+                    *confirmations,
+                    Duration::from_secs(*timeout),
+                )?;
+            }
+        }
+        Commands::Balance { verify } => {
+            let address = keys::keypair_to_address(&keys::load_public_key(None)?)?;
+            let balance = if *verify {
+                verified_account_state(&client, address)?
+            } else {
+                client.account_state(address)?
+            };
+            println!(
+                "Account Balance   : {} $ZEN",
+                keys::format_amount(balance.balance)
+            );
+            println!("Transaction Index : {:?}", balance.transaction_index);
+        }
+        Commands::BumpFee { fee, index } => {
+            let key = keys::load_keypair(None)?;
+            let sender = keys::keypair_to_address(&key)?;
+            let pending = client.pending(sender)?;
+            let mut transaction = match index {
+                Some(index) => pending.into_iter().find(|t| t.index == *index),
+                None => pending.into_iter().min_by_key(|t| t.index),
+            }
+            .ok_or_else(|| Error::Network("No such pending transaction".to_string()))?;
+            // Only re-sign what this key signed before, not whatever the node made of it.
+            if transaction.sender != sender || !transaction.is_signature_valid() {
+                return Err(Error::Network(
+                    "Node returned a pending transaction that wasn't signed by this key"
+                        .to_string(),
+                ));
+            }
+            let minimum = mempool::min_replacement_fee(transaction.fee);
+            if *fee < minimum {
+                return Err(ValidationError::ReplacementFeeTooLow {
+                    pending_fee: transaction.fee,
+                    fee: *fee,
+                    minimum,
+                }
+                .into());
+            }
+            println!("Replacing: {}", transaction);
+            println!(
+                "New fee  : {} $ZEN (was {} $ZEN)",
+                keys::format_amount(*fee),
+                keys::format_amount(transaction.fee)
+            );
+            transaction.fee = *fee;
+            transaction.sign(&key)?;
+            broadcast(&transaction, &all_clients);
+            println!("Transaction id: {}", keys::format_hash(&transaction.id()));
+        }
+        Commands::History { address } => {
+            let address = match address {
+                Some(address) => keys::parse_address(address)?,
+                None => keys::keypair_to_address(&keys::load_public_key(None)?)?,
+            };
+            let history = client.history(address)?;
+            if history.is_empty() {
+                println!("No transactions for {}", keys::format_address(&address));
+            }
+            for entry in history {
+                println!("Block {:>8} : {}", entry.height, entry.transaction);
+            }
+        }
+        Commands::Supply => match client.send(ServerNetworkMessage::GetSupply)? {
+            ClientNetworkMessage::Supply {
+                height,
+                issued,
+                max,
+            } => {
+                println!("Block Height  : {}", height);
+                println!("Issued Supply : {} $ZEN", keys::format_amount(issued));
+                println!("Max Supply    : {} $ZEN", keys::format_amount(max));
+            }
+            msg => return Err(unexpected_response(msg)),
+        },
+        Commands::Chain { chain } => match chain {
+            ChainCommands::Info => match client.send(ServerNetworkMessage::GetChainInfo)? {
+                ClientNetworkMessage::ChainInfo {
+                    height,
+                    tip,
+                    difficulty,
+                    issued,
+                    max,
+                } => {
+                    println!("Block Height  : {}", height);
+                    println!("Tip           : {}", keys::format_hash(&tip));
+                    println!("Difficulty    : {} bits", difficulty);
+                    println!("Issued Supply : {} $ZEN", keys::format_amount(issued));
+                    println!("Max Supply    : {} $ZEN", keys::format_amount(max));
+                }
+                msg => return Err(unexpected_response(msg)),
+            },
+        },
+        Commands::Block { id } => {
+            let block = match client.block(parse_block_id(id)?)? {
+                Some(block) => block,
+                None => {
+                    println!("Block {} unknown to node {}", id, client.address);
+                    return Ok(());
+                }
+            };
+            println!("Block Height : {}", block.index);
+            println!("Hash         : {}", keys::format_hash(&block.get_hash()));
+            println!("Previous     : {}", keys::format_hash(&block.prev_hash));
+            println!("Miner        : {}", keys::format_address(&block.miner));
+            println!("Reward       : {} $ZEN", keys::format_amount(block.reward));
+            println!("Fees         : {} $ZEN", keys::format_amount(block.fees()));
+            println!("Difficulty   : {} bits", block.difficulty);
+            println!("Nonce        : {}", script::to_hex(&block.nonce));
+            println!("Merkle Root  : {}", keys::format_hash(&block.merkle_root));
+            println!("State Root   : {}", keys::format_hash(&block.state_root));
+            println!("Transactions : {}", block.transactions.len());
+            for transaction in &block.transactions {
+                println!("{}  {}", keys::format_hash(&transaction.id()), transaction);
+            }
+        }
+        Commands::Address { address, recent } => {
+            let address = keys::parse_address(address)?;
+            let info = client.address_info(address, *recent)?;
+            println!("Address           : {}", keys::format_address(&address));
+            println!(
+                "Account Balance   : {} $ZEN",
+                keys::format_amount(info.state.balance)
             );
+            println!("Transaction Index : {}", info.state.transaction_index);
+            for transaction in &info.pending {
+                println!("{:<14} : {}", "Pending", transaction);
+            }
+            for entry in info.recent.iter().rev() {
+                println!("Block {:>8} : {}", entry.height, entry.transaction);
+            }
         }
-        Commands::Send { to, amount } => {
-            for client in &all_clients {
-                if let Err(msg) = Transaction::send(to, *amount, &client) {
-                    println!("Node {} Error: {}", client.address, msg);
-                } else {
-                    println!("Transaction sent to node: {}", client.address);
+        Commands::Miner { miner } => {
+            let message = match miner {
+                MinerCommands::Start => ServerNetworkMessage::StartMining,
+                MinerCommands::Stop => ServerNetworkMessage::StopMining,
+                MinerCommands::Payout { address } => {
+                    ServerNetworkMessage::SetPayoutAddress(keys::parse_address(address)?)
+                }
+            };
+            match client.send(message)? {
+                ClientNetworkMessage::Ack => {
+                    println!("Miner updated on node: {}", client.address)
                 }
+                msg => return Err(unexpected_response(msg)),
             }
-            println!("Sent {} $ZEN to: {}", amount, to);
         }
-        Commands::Balance => {
-            let address = keys::keypair_to_address(&keys::load_keypair(None));
-            match client.account_state(address) {
-                Ok(balance) => {
-                    println!("Account Balance   : {:?} $ZEN", balance.balance);
-                    println!("Transaction Index : {:?}", balance.transaction_index);
+        Commands::Tx { tx } => match tx {
+            TxCommands::Create {
+                to,
+                amount,
+                batch,
+                fee,
+                from,
+                policy,
+                script,
+                index,
+                not_before,
+                valid_until,
+                memo,
+                out,
+            } => {
+                let policy = match policy {
+                    Some(policy) => Some(MultisigPolicy::load(policy)?),
+                    None => None,
+                };
+                let script = match script {
+                    Some(script) => Some(load_script(script)?),
+                    None => None,
+                };
+                let sender = match (from, &policy, &script) {
+                    (_, Some(policy), _) => policy.address(),
+                    (_, _, Some(script)) => script.address(),
+                    (Some(from), None, None) => keys::parse_address(from)?,
+                    (None, None, None) => keys::keypair_to_address(&keys::load_public_key(None)?)?,
+                };
+                let index = match index {
+                    Some(index) => *index,
+                    None => next_index(&client, sender)?,
+                };
+                let payment = payment(to, amount, batch)?;
+                let mut transaction = match (policy, script) {
+                    (Some(policy), _) => Transaction::new_multisig(policy, payment, index),
+                    (_, Some(script)) => Transaction::new_script(script, payment, index),
+                    (None, None) => Transaction::with_payment(sender, payment, index),
+                };
+                transaction.fee = *fee;
+                transaction.not_before = *not_before;
+                transaction.valid_until = *valid_until;
+                transaction.memo = memo.as_ref().map(|memo| memo.as_bytes().to_vec());
+                transaction.check_memo()?;
+                transaction.save(out)?;
+                println!("Unsigned transaction written to {}", out.display());
+            }
+            TxCommands::Sign { file, key } => {
+                let mut transaction = Transaction::load(file)?;
+                transaction.sign(&keys::load_keypair(key.clone())?)?;
+                transaction.save(file)?;
+                println!("Transaction signed: {}", transaction);
+            }
+            TxCommands::Witness { file, data } => {
+                let item = data
+                    .strip_prefix("0x")
+                    .and_then(script::parse_hex)
+                    .ok_or_else(|| Error::Config(format!("Invalid hex data '{}'", data)))?;
+                let mut transaction = Transaction::load(file)?;
+                transaction.add_witness(item)?;
+                transaction.save(file)?;
+                println!("Witness item added to {}", file.display());
+            }
+            TxCommands::Combine { files, out } => {
+                let mut transaction = Transaction::load(&files[0])?;
+                for file in &files[1..] {
+                    transaction.combine(&Transaction::load(file)?)?;
+                }
+                transaction.save(out)?;
+                println!("Combined transaction written to {}", out.display());
+            }
+            TxCommands::Inspect { file } => {
+                let transaction = Transaction::load(file)?;
+                println!("Chain ID  : {}", transaction.chain_id);
+                println!("From      : {}", keys::format_address(&transaction.sender));
+                for output in transaction.outputs() {
+                    println!(
+                        "To        : {}  {} $ZEN",
+                        keys::format_address(&output.recipient),
+                        keys::format_amount(output.amount)
+                    );
+                }
+                if let Some(total) = transaction.total_amount() {
+                    println!("Amount    : {} $ZEN", keys::format_amount(total));
+                }
+                println!("Fee       : {} $ZEN", keys::format_amount(transaction.fee));
+                println!("Index     : {}", transaction.index);
+                if let Some(not_before) = transaction.not_before {
+                    println!("Not Before: block {}", not_before);
+                }
+                if let Some(valid_until) = transaction.valid_until {
+                    println!("Expires   : after block {}", valid_until);
+                }
+                if let Some(memo) = transaction.memo_text() {
+                    println!("Memo      : {}", memo);
+                }
+                if let Authorization::Multisig { policy, signatures } = &transaction.authorization {
+                    println!(
+                        "Multisig  : {} of {} signatures",
+                        signatures.len(),
+                        policy.threshold
+                    );
+                }
+                if let Authorization::Script { script, witness } = &transaction.authorization {
+                    println!("Script    : {}", script);
+                    println!("Witness   : {} items", witness.len());
+                }
+                let signature = match (transaction.is_signed(), transaction.is_signature_valid()) {
+                    (false, _) => "not signed",
+                    (true, true) => "valid",
+                    (true, false) => "INVALID",
+                };
+                println!("Signature : {}", signature);
+                if transaction.is_signed() {
+                    println!("Id        : {}", keys::format_hash(&transaction.id()));
+                }
+            }
+            TxCommands::Broadcast { file } => {
+                let transaction = Transaction::load(file)?;
+                if !transaction.is_signature_valid() {
+                    return Err(ValidationError::InvalidSignature.into());
+                }
+                broadcast(&transaction, &all_clients);
+                println!("Transaction id: {}", keys::format_hash(&transaction.id()));
+            }
+            TxCommands::Status { id } => match client.transaction_status(keys::parse_hash(id)?)? {
+                TransactionStatus::Pending => println!("Pending"),
+                TransactionStatus::Confirmed {
+                    height,
+                    confirmations,
+                } => println!(
+                    "Confirmed in block {} ({} confirmations)",
+                    height, confirmations
+                ),
+                TransactionStatus::Unknown => {
+                    println!("Unknown to node {}", client.address)
+                }
+            },
+            TxCommands::Verify { id } => {
+                let id = keys::parse_hash(id)?;
+                let headers = client.headers()?;
+                block::verify_headers(&headers)?;
+                let proof = match client.transaction_proof(id)? {
+                    Some(proof) => proof,
+                    None => {
+                        println!("Not mined according to node {}", client.address);
+                        return Ok(());
+                    }
+                };
+                let header = headers.iter().find(|h| h.index == proof.height);
+                match header {
+                    Some(header) if proof.transaction.id() == id && proof.verify(header) => {}
+                    _ => return Err(ConsensusError::InvalidInclusionProof.into()),
+                }
+                println!("{}", proof.transaction);
+                println!(
+                    "Verified in block {} {} ({} confirmations, {} headers checked)",
+                    proof.height,
+                    keys::format_hash(&proof.block_hash),
+                    headers.len() as u128 - proof.height + 1,
+                    headers.len()
+                );
+            }
+        },
+        Commands::Script { script } => match script {
+            ScriptCommands::Address { file } => {
+                let script = load_script(file)?;
+                println!("Script  : {}", script);
+                println!("Address : {}", keys::format_address(&script.address()));
+            }
+            ScriptCommands::Hash { data } => {
+                let data = data
+                    .strip_prefix("0x")
+                    .and_then(script::parse_hex)
+                    .ok_or_else(|| Error::Config(format!("Invalid hex data '{}'", data)))?;
+                let hash = hash(MessageDigest::sha3_256(), &data)
+                    .map_err(|e| Error::Config(e.to_string()))?;
+                println!("0x{}", script::to_hex(&hash));
+            }
+        },
+        Commands::Multisig { multisig } => match multisig {
+            MultisigCommands::Create {
+                threshold,
+                keys,
+                out,
+            } => {
+                let public_keys = keys
+                    .iter()
+                    .map(|key| load_participant_key(key))
+                    .collect::<Result<Vec<_>>>()?;
+                let policy = MultisigPolicy::new(*threshold, public_keys)?;
+                policy.save(out)?;
+                println!(
+                    "{} of {} multisig address: {}",
+                    policy.threshold,
+                    policy.public_keys.len(),
+                    keys::format_address(&policy.address())
+                );
+            }
+            MultisigCommands::Info { file } => {
+                let policy = MultisigPolicy::load(file)?;
+                println!("Address   : {}", keys::format_address(&policy.address()));
+                println!(
+                    "Threshold : {} of {}",
+                    policy.threshold,
+                    policy.public_keys.len()
+                );
+                for (index, key) in policy.public_keys.iter().enumerate() {
+                    let (key_type, _) = keys::decode_public_key(key)?;
+                    println!(
+                        "Key {:<5} : {} ({})",
+                        index,
+                        keys::format_address(&keys::public_key_to_address(key)?),
+                        key_type
+                    );
                 }
-                Err(err) => println!("Error: {:?}", err),
             }
+        },
+    }
+    Ok(())
+}
+
+/// Builds the payment of `send` and `tx create` from either `--to` and `--amount` or a batch file.
+fn payment(to: &Option<String>, amount: &Option<u128>, batch: &Option<PathBuf>) -> Result<Payment> {
+    if let Some(batch) = batch {
+        return Payment::batch(read_batch(batch)?);
+    }
+    match (to, amount) {
+        (Some(to), Some(amount)) => Ok(Payment::Single(Output {
+            recipient: keys::parse_address(to)?,
+            amount: *amount,
+        })),
+        _ => Err(Error::Config(
+            "Needs --to and --amount, or --batch".to_string(),
+        )),
+    }
+}
+
+/// Reads `address,amount` lines. Empty lines and lines starting with `#` are skipped.
+fn read_batch(path: &PathBuf) -> Result<Vec<Output>> {
+    let text = fs::read_to_string(path).map_err(|e| Error::Storage(e.to_string()))?;
+    text.lines()
+        .enumerate()
+        .map(|(number, line)| (number, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| {
+            let (address, amount) = line.split_once(',').ok_or_else(|| {
+                Error::Storage(format!(
+                    "{} line {}: expected address,amount",
+                    path.display(),
+                    number + 1
+                ))
+            })?;
+            Ok(Output {
+                recipient: keys::parse_address(address.trim())?,
+                amount: keys::parse_amount(amount.trim())?,
+            })
+        })
+        .collect()
+}
+
+/// Reads a script file. Words of the form `@name` are replaced by the public key of that key.
+fn load_script(path: &PathBuf) -> Result<Script> {
+    let text = fs::read_to_string(path).map_err(|e| Error::Storage(e.to_string()))?;
+    let words = text
+        .split_whitespace()
+        .map(|word| match word.strip_prefix('@') {
+            Some(name) => Ok(format!(
+                "0x{}",
+                script::to_hex(&load_participant_key(name)?)
+            )),
+            None => Ok(word.to_string()),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Script::parse(&words.join(" "))
+}
+
+/// Loads the public key of a multisig participant or script signer from a PEM file, or from the
+/// keystore by name.
+fn load_participant_key(key: &str) -> Result<PublicKey> {
+    let path = PathBuf::from(key);
+    let public_key = if path.is_file() {
+        let pem = fs::read(&path).map_err(|e| Error::Storage(e.to_string()))?;
+        PKey::public_key_from_pem(&pem)
+            .map_err(|_| Error::Storage(format!("{} is not a PEM public key", path.display())))?
+    } else {
+        keys::load_public_key(Some(key.to_string()))?
+    };
+    keys::encode_public_key(&public_key)
+}
+
+/// The first index of `sender` that is neither confirmed nor pending, so several transactions
+/// can be sent without waiting for blocks.
+fn next_index(client: &BlockchainClient, sender: Address) -> Result<u128> {
+    let mut index = client.account_state(sender)?.transaction_index + 1;
+    let pending = client.pending(sender)?;
+    while pending.iter().any(|t| t.index == index) {
+        index += 1;
+    }
+    Ok(index)
+}
+
+/// Submits `transaction` to every node and returns the ones that accepted it.
+fn broadcast<'a>(
+    transaction: &Transaction,
+    clients: &'a [BlockchainClient],
+) -> Vec<&'a BlockchainClient> {
+    let mut accepted = Vec::new();
+    for client in clients {
+        if let Err(err) = transaction.submit(client) {
+            println!("Node {} Error: {}", client.address, err);
+        } else {
+            println!("Transaction sent to node: {}", client.address);
+            accepted.push(client);
+        }
+    }
+    accepted
+}
+
+/// Polls `client` until `transaction` is `confirmations` blocks deep. Fails on timeout, or if
+/// the transaction leaves the chain or the node's mempool after it was seen there.
+fn wait_for_confirmations(
+    client: &BlockchainClient,
+    transaction: &Transaction,
+    confirmations: u128,
+    timeout: Duration,
+) -> Result<()> {
+    let id = transaction.id();
+    let start = Instant::now();
+    let mut last = TransactionStatus::Unknown;
+    loop {
+        let status = client.transaction_status(id)?;
+        match (&last, &status) {
+            (TransactionStatus::Confirmed { height, .. }, TransactionStatus::Pending)
+            | (TransactionStatus::Confirmed { height, .. }, TransactionStatus::Unknown) => {
+                return Err(Error::Network(format!(
+                    "Transaction was removed from block {} by a chain reorganization",
+                    height
+                )));
+            }
+            (TransactionStatus::Pending, TransactionStatus::Unknown) => {
+                return Err(Error::Network(
+                    "Transaction was dropped from the mempool".to_string(),
+                ));
+            }
+            (
+                _,
+                TransactionStatus::Confirmed {
+                    confirmations: depth,
+                    ..
+                },
+            ) if *depth >= confirmations => {
+                println!("Confirmed ({} confirmations)", depth);
+                return Ok(());
+            }
+            _ => {}
+        }
+        if status != last {
+            match status {
+                TransactionStatus::Pending => println!("Pending..."),
+                TransactionStatus::Confirmed {
+                    height,
+                    confirmations: depth,
+                } => println!(
+                    "In block {}, {} of {} confirmations...",
+                    height, depth, confirmations
+                ),
+                TransactionStatus::Unknown => {}
+            }
+        }
+        if start.elapsed() > timeout {
+            return Err(Error::Network(format!(
+                "Timed out waiting for {} confirmations",
+                confirmations
+            )));
+        }
+        last = status;
+        thread::sleep(Duration::from_secs(2));
+    }
+}
+
+/// A height if `id` is a number, otherwise a block hash.
+fn parse_block_id(id: &str) -> Result<BlockId> {
+    match id.parse() {
+        Ok(height) if id.len() < 64 => Ok(BlockId::Height(height)),
+        _ => Ok(BlockId::Hash(keys::parse_hash(id)?)),
+    }
+}
+
+/// Fetches the state of `address` at the chain tip and checks it against the node's block headers.
+fn verified_account_state(client: &BlockchainClient, address: Address) -> Result<AccountState> {
+    // The proof comes first so the headers fetched after it include its block.
+    let proof = client.account_proof(address)?;
+    let headers = client.headers()?;
+    block::verify_headers(&headers)?;
+    // Nodes prove absent accounts too, so only an empty chain may come without a proof.
+    let proof = match proof {
+        Some(proof) => proof,
+        None if headers.is_empty() => return Ok(AccountState::new(address)),
+        None => return Err(ConsensusError::InvalidAccountProof.into()),
+    };
+    match headers.iter().find(|h| h.index == proof.height) {
+        Some(header) if proof.state.address == address && proof.verify(header) => {
+            println!(
+                "Verified against block {} of {}",
+                proof.height,
+                headers.len()
+            );
+            Ok(proof.state)
         }
+        _ => Err(ConsensusError::InvalidAccountProof.into()),
+    }
+}
+
+fn unexpected_response(msg: ClientNetworkMessage) -> Error {
+    match msg {
+        ClientNetworkMessage::Error(err) => err,
+        msg => Error::Network(format!("Unexpected response: {:?}", msg)),
     }
 }
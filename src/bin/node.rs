@@ -1,27 +1,116 @@
-use clap::Parser;
-use zenchain::blockchain::BlockChain;
+use std::{path::PathBuf, process};
+
+use clap::{Parser, Subcommand};
+use zenchain::{
+    blockchain::{BlockChain, MinerOptions},
+    config::{self, Config},
+    error::Result,
+    keys,
+};
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 #[clap(propagate_version = true)]
 struct Cli {
+    #[clap(subcommand)]
+    command: Option<Commands>,
+
+    /// Port to listen on. Defaults to the config file's port or 8888.
     #[clap(short, long, value_parser)]
-    port: u16,
+    port: Option<u16>,
 
     #[clap(short, long, value_parser)]
     key: Option<String>,
+
+    /// Directory holding the chain data, keys and nodes.txt. Defaults to the current directory.
+    #[clap(long, value_parser)]
+    data_dir: Option<PathBuf>,
+
+    /// Config file to use instead of zenchain.toml in the data directory.
+    #[clap(short, long, value_parser)]
+    config: Option<PathBuf>,
+
+    /// Run a relay/wallet node without mining. Mining can be started later via the client.
+    #[clap(long, value_parser)]
+    no_mine: bool,
+
+    #[clap(long, value_parser)]
+    mine_threads: Option<usize>,
+
+    /// Address that receives block rewards. Defaults to the address of --key.
+    #[clap(long, value_parser)]
+    payout_address: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Write the main chain to a bootstrap file, in height order
+    ExportChain {
+        #[clap(value_parser)]
+        file: PathBuf,
+    },
+    /// Validate the blocks of a bootstrap file and add them to the chain data. Stop the node
+    /// first.
+    ImportChain {
+        #[clap(value_parser)]
+        file: PathBuf,
+    },
 }
 
 fn main() {
-    run_node();
+    if let Err(err) = run_node() {
+        println!("Error: {}", err);
+        process::exit(1);
+    }
 }
 
-fn run_node() {
+fn run_node() -> Result<()> {
     let cli = Cli::parse();
+    config::init(Config::load(
+        cli.config.as_deref(),
+        cli.data_dir.as_deref(),
+    )?);
+    let config = config::get();
+
+    match cli.command {
+        Some(Commands::ExportChain { file }) => {
+            let count = BlockChain::load()?.export_chain(&file)?;
+            println!("Exported {} blocks to {}", count, file.display());
+            return Ok(());
+        }
+        Some(Commands::ImportChain { file }) => {
+            let mut chain = BlockChain::load()?;
+            let count = chain.import_chain(&file)?;
+            chain.save()?;
+            println!("Imported {} blocks from {}", count, file.display());
+            return Ok(());
+        }
+        None => {}
+    }
 
     println!("Running zenchain node.");
 
-    let chain = BlockChain::load();
+    let enabled = !cli.no_mine && config.mining.enabled;
+    let key = cli.key.or_else(|| config.mining.key.clone());
+    let payout = match cli
+        .payout_address
+        .or_else(|| config.mining.payout_address.clone())
+    {
+        Some(address) => Some(keys::parse_address(&address)?),
+        None if enabled || key.is_some() || keys::get_default_keypair().is_some() => {
+            Some(keys::keypair_to_address(&keys::load_public_key(key)?)?)
+        }
+        None => None,
+    };
+
+    let options = MinerOptions {
+        enabled,
+        threads: cli.mine_threads.unwrap_or(config.mining.threads).max(1),
+        payout,
+    };
+
+    let chain = BlockChain::load()?;
 
-    chain.run(cli.port, cli.key);
+    chain.run(cli.port.unwrap_or(config.port), options);
+    Ok(())
 }
@@ -1,14 +1,28 @@
+use std::thread;
+
 use openssl::hash::{Hasher, MessageDigest};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 
 use crate::{
     blockchain::{BlockChain, World},
+    error::{ConsensusError, Error, Result},
+    merkle,
     transaction::Transaction,
-    types::{Address, Hash},
+    types::{Address, Hash, COIN},
 };
 
+#[cfg(not(test))]
 pub const DIFFICULTY_BITS: u32 = 26;
+/// Unit tests mine real blocks, so they use a trivial difficulty.
+#[cfg(test)]
+pub const DIFFICULTY_BITS: u32 = 4;
+pub const INITIAL_BLOCK_REWARD: u128 = 100 * COIN;
+pub const HALVING_INTERVAL: u128 = 1_000_000;
+/// Total number of base units that will ever be issued through block rewards.
+pub const MAX_SUPPLY: u128 = issued_supply(u128::MAX);
+/// Largest encoded block the node reads from a chain export.
+pub const MAX_BLOCK_SIZE: usize = 32 * 1024 * 1024;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Block {
@@ -17,33 +31,100 @@ pub struct Block {
     pub nonce: [u8; 32],
     pub miner: Address,
     pub reward: u128,
+    /// Root of the Merkle tree over the full transaction hashes, see [`merkle::root`].
+    pub merkle_root: Hash,
+    /// Root of the account states after this block, see [`World::root`].
+    pub state_root: Hash,
     pub transactions: Vec<Transaction>,
     pub difficulty: u32,
 }
 
+/// Everything of a block except the transactions, which the header commits to through
+/// `merkle_root`. `state_root` commits to the account states after the block. The block hash is
+/// the hash of its header, so a chain of headers can be checked without downloading any
+/// transactions.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockHeader {
+    pub index: u128,
+    pub prev_hash: Hash,
+    pub nonce: [u8; 32],
+    pub miner: Address,
+    pub reward: u128,
+    pub merkle_root: Hash,
+    pub state_root: Hash,
+    pub difficulty: u32,
+}
+
 impl Block {
-    pub fn new(parent: Option<&Block>, transactions: &Vec<Transaction>, miner: &Address) -> Block {
+    /// A block on top of `parent`, whose account states are `world`.
+    pub fn new(
+        parent: Option<&Block>,
+        world: &World,
+        transactions: &[Transaction],
+        miner: &Address,
+    ) -> Block {
         let index = parent.as_ref().map_or(1, |b| b.index + 1);
-        Block {
+        let mut block = Block {
             index,
             prev_hash: parent.as_ref().map_or([0u8; 32], |b| b.get_hash()),
-            transactions: transactions.clone(),
+            merkle_root: merkle::root(
+                &transactions
+                    .iter()
+                    .map(|t| t.full_hash())
+                    .collect::<Vec<_>>(),
+            ),
+            state_root: [0u8; 32],
+            transactions: transactions.to_vec(),
             difficulty: DIFFICULTY_BITS,
             reward: get_block_reward(index),
             miner: miner.clone(),
             nonce: [0u8; 32],
+        };
+        let mut state = world.clone();
+        for transaction in transactions {
+            state.update_on_transaction(transaction);
         }
+        state.update_on_block(&block);
+        block.state_root = state.root();
+        block
     }
 
-    pub fn get_hash(&self) -> Hash {
-        let mut hasher = Hasher::new(MessageDigest::sha3_256()).unwrap();
-        let data = bincode::serialize(self).unwrap();
-        hasher.update(&data).unwrap();
-        let hash = hasher.finish().unwrap();
+    /// Sum of the fees of all transactions, paid to the miner on top of the reward.
+    pub fn fees(&self) -> u128 {
+        self.transactions.iter().map(|t| t.fee).sum()
+    }
 
-        let mut hash_bytes: Hash = [0u8; 32];
-        hash_bytes.copy_from_slice(&hash);
-        hash_bytes
+    pub fn header(&self) -> BlockHeader {
+        BlockHeader {
+            index: self.index,
+            prev_hash: self.prev_hash,
+            nonce: self.nonce,
+            miner: self.miner,
+            reward: self.reward,
+            merkle_root: self.merkle_root,
+            state_root: self.state_root,
+            difficulty: self.difficulty,
+        }
+    }
+
+    /// The leaves of the Merkle tree, see [`Transaction::full_hash`].
+    pub fn transaction_hashes(&self) -> Vec<Hash> {
+        self.transactions.iter().map(|t| t.full_hash()).collect()
+    }
+
+    /// Proves that the transaction at `position` is part of this block.
+    pub fn prove(&self, position: usize) -> Option<merkle::InclusionProof> {
+        let proof = merkle::prove(&self.transaction_hashes(), position)?;
+        Some(merkle::InclusionProof {
+            transaction: self.transactions[position].clone(),
+            height: self.index,
+            block_hash: self.get_hash(),
+            proof,
+        })
+    }
+
+    pub fn get_hash(&self) -> Hash {
+        self.header().get_hash()
     }
 
     pub fn mine(&mut self, attempts: u128) -> bool {
@@ -57,17 +138,39 @@ impl Block {
         false
     }
 
+    pub fn mine_parallel(&mut self, threads: usize, attempts: u128) -> bool {
+        if threads <= 1 {
+            return self.mine(attempts);
+        }
+        let template: &Block = self;
+        let mined = thread::scope(|scope| {
+            let workers = (0..threads)
+                .map(|_| {
+                    scope.spawn(move || {
+                        let mut candidate = template.clone();
+                        candidate.mine(attempts).then_some(candidate)
+                    })
+                })
+                .collect::<Vec<_>>();
+            workers
+                .into_iter()
+                .filter_map(|worker| worker.join().unwrap())
+                .next()
+        });
+        match mined {
+            Some(block) => {
+                *self = block;
+                true
+            }
+            None => false,
+        }
+    }
+
     fn randomize_nonce(&mut self) {
         self.nonce = rand::thread_rng().gen::<[u8; 32]>();
     }
 
-    pub fn is_valid(&self, blockchain: &BlockChain) -> Result<(), String> {
-        if self.difficulty != DIFFICULTY_BITS {
-            return Err("Invalid difficulty".to_string());
-        }
-        if self.reward != get_block_reward(self.index) {
-            return Err("Invalid reward".to_string());
-        }
+    pub fn is_valid(&self, blockchain: &BlockChain) -> Result<()> {
         let parent = if self.prev_hash == [0u8; 32] {
             match blockchain.blocks.get(&self.prev_hash) {
                 Some(block) => Some(block),
@@ -76,45 +179,133 @@ impl Block {
         } else {
             match blockchain.blocks.get(&self.prev_hash) {
                 Some(block) => Some(block),
-                None => return Err("Invalid prev_hash. Parent not found".to_string()),
+                None => return Err(ConsensusError::ParentNotFound.into()),
             }
         };
 
-        if self.index != parent.map_or(0, |p| p.index) + 1 {
-            return Err(format!(
-                "Invalid index. Should be {}, but is {}",
-                parent.map_or(0, |p| p.index) + 1,
-                self.index
-            ));
+        let chain = blockchain.get_chain_from_leaf(self.prev_hash);
+        let mut world = World::from_chain(&chain);
+        self.apply(parent, &mut world)
+    }
+
+    /// Checks the block on top of `parent`, whose account states are `world`, and moves `world`
+    /// on to the states after the block. `world` is unusable if the block is invalid.
+    pub fn apply(&self, parent: Option<&Block>, world: &mut World) -> Result<()> {
+        self.header()
+            .is_valid(parent.map(|p| p.header()).as_ref())?;
+        if self.merkle_root != merkle::root(&self.transaction_hashes()) {
+            return Err(ConsensusError::InvalidMerkleRoot.into());
         }
 
-        let hash = self.get_hash();
-        if !hash_valid(self.difficulty, &hash) {
-            return Err("Invalid hash. Did you really do the work?".to_string());
+        for (position, transaction) in self.transactions.iter().enumerate() {
+            match transaction
+                .is_valid_at(self.index)
+                .and_then(|()| transaction.is_valid(world))
+            {
+                Ok(()) => {}
+                Err(Error::Validation(reason)) => {
+                    return Err(ConsensusError::InvalidTransaction { position, reason }.into())
+                }
+                Err(err) => return Err(err),
+            }
+            world.update_on_transaction(transaction);
         }
+        world.update_on_block(self);
+        if self.state_root != world.root() {
+            return Err(ConsensusError::InvalidStateRoot.into());
+        }
+        Ok(())
+    }
+}
 
-        let chain = blockchain.get_chain_from_leaf(self.prev_hash);
-        let mut world = World::from_chain(&chain);
+impl BlockHeader {
+    pub fn get_hash(&self) -> Hash {
+        let mut hasher = Hasher::new(MessageDigest::sha3_256()).unwrap();
+        let data = bincode::serialize(self).unwrap();
+        hasher.update(&data).unwrap();
+        let hash = hasher.finish().unwrap();
+
+        let mut hash_bytes: Hash = [0u8; 32];
+        hash_bytes.copy_from_slice(&hash);
+        hash_bytes
+    }
 
-        for transaction in &self.transactions {
-            if let Err(message) = transaction.is_valid(&world) {
-                return Err(format!(
-                    "Invalid transaction: {}. Error: {}",
-                    transaction, message
-                ));
+    /// Checks the proof of work and that the header follows `parent`, or starts the chain if
+    /// there is no parent. Says nothing about the transactions.
+    pub fn is_valid(&self, parent: Option<&BlockHeader>) -> Result<()> {
+        if self.difficulty != DIFFICULTY_BITS {
+            return Err(ConsensusError::InvalidDifficulty {
+                expected: DIFFICULTY_BITS,
+                actual: self.difficulty,
+            }
+            .into());
+        }
+        if self.reward != get_block_reward(self.index) {
+            return Err(ConsensusError::InvalidReward {
+                expected: get_block_reward(self.index),
+                actual: self.reward,
+            }
+            .into());
+        }
+        if self.prev_hash != parent.map_or([0u8; 32], |p| p.get_hash()) {
+            return Err(ConsensusError::ParentNotFound.into());
+        }
+        if self.index != parent.map_or(0, |p| p.index) + 1 {
+            return Err(ConsensusError::InvalidIndex {
+                expected: parent.map_or(0, |p| p.index) + 1,
+                actual: self.index,
             }
-            world.update_on_transaction(&transaction);
+            .into());
         }
-        world.update_on_block(&self);
 
-        return Ok(());
+        let hash = self.get_hash();
+        if !hash_valid(self.difficulty, &hash) {
+            return Err(ConsensusError::InvalidHash.into());
+        }
+        Ok(())
     }
 }
 
-fn get_block_reward(index: u128) -> u128 {
-    let power = index / 1_000_000;
-    let reward_multiplier = 0.5f64.powf(power as f64);
-    return reward_multiplier as u128 * 100;
+/// Checks a chain of headers from the first block on, as a light client does before trusting
+/// anything a node says about the blocks.
+pub fn verify_headers(headers: &[BlockHeader]) -> Result<()> {
+    let mut parent = None;
+    for header in headers {
+        header.is_valid(parent)?;
+        parent = Some(header);
+    }
+    Ok(())
+}
+
+pub const fn get_block_reward(index: u128) -> u128 {
+    let halvings = index / HALVING_INTERVAL;
+    if halvings >= u128::BITS as u128 {
+        return 0;
+    }
+    INITIAL_BLOCK_REWARD >> halvings
+}
+
+/// Sum of all block rewards for blocks `1..=height`.
+pub const fn issued_supply(height: u128) -> u128 {
+    let mut supply = 0;
+    let mut halvings = 0;
+    while halvings < u128::BITS as u128 {
+        let reward = INITIAL_BLOCK_REWARD >> halvings;
+        // Block indices start at 1, so the first era is one block short.
+        let first = if halvings == 0 {
+            1
+        } else {
+            halvings * HALVING_INTERVAL
+        };
+        if reward == 0 || height < first {
+            break;
+        }
+        let last = (halvings + 1) * HALVING_INTERVAL - 1;
+        let last = if height < last { height } else { last };
+        supply += (last - first + 1) * reward;
+        halvings += 1;
+    }
+    supply
 }
 
 fn hash_valid(difficulty: u32, hash: &Hash) -> bool {
@@ -137,3 +328,47 @@ fn hash_valid(difficulty: u32, hash: &Hash) -> bool {
 
     return special_bit == 0;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_reward_halves_every_interval() {
+        assert_eq!(get_block_reward(0), INITIAL_BLOCK_REWARD);
+        assert_eq!(get_block_reward(1), INITIAL_BLOCK_REWARD);
+        assert_eq!(get_block_reward(HALVING_INTERVAL - 1), INITIAL_BLOCK_REWARD);
+        assert_eq!(get_block_reward(HALVING_INTERVAL), INITIAL_BLOCK_REWARD / 2);
+        // 100 $ZEN is 10^10 units, which halves down to a single unit after 33 halvings.
+        assert_eq!(get_block_reward(33 * HALVING_INTERVAL), 1);
+        assert_eq!(get_block_reward(34 * HALVING_INTERVAL), 0);
+        assert_eq!(get_block_reward(u128::MAX), 0);
+    }
+
+    #[test]
+    fn issued_supply_sums_rewards_from_block_one() {
+        assert_eq!(issued_supply(0), 0);
+        assert_eq!(issued_supply(1), INITIAL_BLOCK_REWARD);
+        assert_eq!(
+            issued_supply(HALVING_INTERVAL - 1),
+            (HALVING_INTERVAL - 1) * INITIAL_BLOCK_REWARD
+        );
+        assert_eq!(
+            issued_supply(HALVING_INTERVAL),
+            (HALVING_INTERVAL - 1) * INITIAL_BLOCK_REWARD + INITIAL_BLOCK_REWARD / 2
+        );
+        assert_eq!(issued_supply(34 * HALVING_INTERVAL - 1), MAX_SUPPLY);
+        assert_eq!(issued_supply(34 * HALVING_INTERVAL), MAX_SUPPLY);
+    }
+
+    #[test]
+    fn max_supply() {
+        // The sum of 10^10 >> h for h in 0..34 is 19_999_999_989 units, each paid for
+        // HALVING_INTERVAL blocks, minus the reward of block 0 which is never paid.
+        assert_eq!(
+            MAX_SUPPLY,
+            19_999_999_989 * HALVING_INTERVAL - INITIAL_BLOCK_REWARD
+        );
+        assert_eq!(MAX_SUPPLY, 19_999_989_989_000_000);
+    }
+}
@@ -1,6 +1,7 @@
 use std::{
     collections::{HashMap, HashSet},
-    fs::{read, write},
+    fs::{read, write, File},
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
     path::Path,
     sync::mpsc::{self, Receiver, Sender},
     thread,
@@ -9,26 +10,51 @@ use std::{
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    block::Block,
+    block::{issued_supply, Block, DIFFICULTY_BITS, MAX_BLOCK_SIZE, MAX_SUPPLY},
     client::BlockchainClient,
+    config,
+    error::{ConsensusError, Error, Result, ValidationError},
     keys,
+    mempool::Mempool,
+    merkle::{self, AccountProof, InclusionProof},
     server::{load_nodes, BlockchainServer},
     transaction::Transaction,
-    types::{Address, ClientNetworkMessage, Hash, ServerNetworkMessage},
+    types::{Address, BlockId, ClientNetworkMessage, Hash, ServerNetworkMessage},
 };
 
+/// Starts a chain export file, followed by the format version.
+const EXPORT_MAGIC: &[u8; 8] = b"ZENCHAIN";
+const EXPORT_VERSION: u32 = 1;
+
 enum MinerMessage {
-    NewTransaction(Transaction, World),
-    NewBlock(Block),
+    /// The node's pending transactions changed.
+    Transactions(Vec<Transaction>),
+    /// A new chain tip and the account states after it.
+    NewBlock(Box<Block>, World),
+    Start,
+    Stop,
+    SetPayout(Address),
     // RemoveTransaction(Address, u128),
 }
 
+#[derive(Debug, Clone)]
+pub struct MinerOptions {
+    pub enabled: bool,
+    pub threads: usize,
+    pub payout: Option<Address>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BlockChain {
     pub blocks: HashMap<Hash, Block>,
 
     #[serde(skip)]
     miner: Option<Sender<MinerMessage>>,
+    #[serde(skip)]
+    mempool: Mempool,
+    /// Where the miner pays block rewards, mirrored here to refuse starting without one.
+    #[serde(skip)]
+    payout: Option<Address>,
 }
 
 impl BlockChain {
@@ -36,26 +62,131 @@ impl BlockChain {
         BlockChain {
             blocks: HashMap::new(),
             miner: None,
+            mempool: Mempool::new(),
+            payout: None,
         }
     }
 
-    pub fn save(&self) {
-        let data = bincode::serialize(&self).unwrap();
-        let path = Path::new("zenchain-data.bin");
-        write(path, data).unwrap();
+    pub fn save(&self) -> Result<()> {
+        let data = bincode::serialize(&self).map_err(|e| Error::Storage(e.to_string()))?;
+        let path = config::get().chain_file();
+        write(path, data).map_err(|e| Error::Storage(e.to_string()))
     }
 
-    pub fn load() -> BlockChain {
-        let path = Path::new("zenchain-data.bin");
+    pub fn load() -> Result<BlockChain> {
+        let path = config::get().chain_file();
         if !path.exists() {
-            return BlockChain::new();
+            return Ok(BlockChain::new());
         }
-        let data = read(path).unwrap();
-        let chain: BlockChain = bincode::deserialize(&data).unwrap();
-        chain
+        let data = read(path).map_err(|e| Error::Storage(e.to_string()))?;
+        let chain: BlockChain =
+            bincode::deserialize(&data).map_err(|e| Error::Storage(e.to_string()))?;
+        Ok(chain)
+    }
+
+    /// Writes the main chain to `path` for `import_chain`: the magic and version, then every
+    /// block in height order as a u32 length followed by its bincode encoding. Returns the number
+    /// of blocks written.
+    pub fn export_chain(&self, path: &Path) -> Result<usize> {
+        let file = File::create(path).map_err(|e| Error::Storage(e.to_string()))?;
+        let mut writer = BufWriter::new(file);
+        let chain = self.get_chain();
+        let mut write_all = |data: &[u8]| {
+            writer
+                .write_all(data)
+                .map_err(|e| Error::Storage(e.to_string()))
+        };
+        write_all(EXPORT_MAGIC)?;
+        write_all(&EXPORT_VERSION.to_le_bytes())?;
+        for block in &chain {
+            let data = bincode::serialize(block).map_err(|e| Error::Storage(e.to_string()))?;
+            write_all(&(data.len() as u32).to_le_bytes())?;
+            write_all(&data)?;
+        }
+        writer.flush().map_err(|e| Error::Storage(e.to_string()))?;
+        Ok(chain.len())
     }
 
-    pub fn run(mut self, port: u16, key_name: Option<String>) {
+    /// Adds the blocks of a file written by `export_chain`, validating each one on top of the
+    /// blocks before it. Blocks the chain already has are skipped. Returns the number of new
+    /// blocks.
+    ///
+    /// Stops at the first invalid block. The blocks before it stay in memory, so callers that
+    /// want the import to be all-or-nothing must not save the chain after an error.
+    pub fn import_chain(&mut self, path: &Path) -> Result<usize> {
+        let file = File::open(path).map_err(|e| Error::Storage(e.to_string()))?;
+        let mut reader = BufReader::new(file);
+        let invalid_file = || Error::Storage(format!("{} is not a chain export", path.display()));
+
+        let mut header = [0u8; 12];
+        reader.read_exact(&mut header).map_err(|_| invalid_file())?;
+        if &header[..8] != EXPORT_MAGIC {
+            return Err(invalid_file());
+        }
+        let version = u32::from_le_bytes(header[8..].try_into().unwrap());
+        if version != EXPORT_VERSION {
+            return Err(Error::Storage(format!(
+                "Unsupported chain export version {}",
+                version
+            )));
+        }
+
+        let mut imported = 0;
+        // The states after the last imported block, so consecutive blocks don't rebuild them.
+        let mut tip: Option<(Hash, World)> = None;
+        loop {
+            let mut len_buffer = [0u8; 4];
+            match reader.read_exact(&mut len_buffer) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(Error::Storage(e.to_string())),
+            }
+            let len = u32::from_le_bytes(len_buffer) as usize;
+            if len > MAX_BLOCK_SIZE {
+                return Err(Error::Storage(format!(
+                    "{} holds a block of {} bytes, more than the maximum of {}",
+                    path.display(),
+                    len,
+                    MAX_BLOCK_SIZE
+                )));
+            }
+            let mut data = vec![0u8; len];
+            reader.read_exact(&mut data).map_err(|_| {
+                Error::Storage(format!("{} ends in the middle of a block", path.display()))
+            })?;
+            let block: Block = bincode::deserialize(&data).map_err(|_| invalid_file())?;
+
+            let hash = block.get_hash();
+            if self.blocks.contains_key(&hash) {
+                continue;
+            }
+            let invalid = |reason| ConsensusError::InvalidBlock {
+                index: block.index,
+                reason: Box::new(reason),
+            };
+            let parent = if block.prev_hash == [0u8; 32] {
+                None
+            } else {
+                let parent = self.blocks.get(&block.prev_hash);
+                Some(parent.ok_or_else(|| invalid(ConsensusError::ParentNotFound))?)
+            };
+            let mut world = match tip.take() {
+                Some((tip_hash, world)) if tip_hash == block.prev_hash => world,
+                _ => World::from_chain(&self.get_chain_from_leaf(block.prev_hash)),
+            };
+            block.apply(parent, &mut world).map_err(|err| match err {
+                Error::Consensus(reason) => invalid(reason).into(),
+                err => err,
+            })?;
+            self.blocks.insert(hash, block);
+            tip = Some((hash, world));
+            imported += 1;
+        }
+        Ok(imported)
+    }
+
+    pub fn run(mut self, port: u16, options: MinerOptions) {
+        self.payout = options.payout;
         let (on_message_send, on_message_recv) = mpsc::channel::<ServerNetworkMessage>();
         let (return_send, return_recv) = mpsc::channel::<ClientNetworkMessage>();
         let longest_chain = BlockChain::get_longest_chain_from_network();
@@ -74,14 +205,12 @@ impl BlockChain {
         }
 
         let chain = self.get_chain();
+        let world = World::from_chain(&chain);
         let last = chain.into_iter().last();
         println!("Last block index: {}", last.as_ref().map_or(0, |b| b.index));
 
-        let keypair = keys::load_keypair(key_name);
-        let address = keys::keypair_to_address(&keypair);
-
         thread::spawn(move || {
-            BlockChain::run_miner(miner_recv, last, address);
+            BlockChain::run_miner(miner_recv, last, world, options);
         });
 
         self.miner = Some(miner_send);
@@ -92,25 +221,47 @@ impl BlockChain {
         }
     }
 
-    fn run_miner(channel: Receiver<MinerMessage>, parent: Option<Block>, miner: Address) {
+    fn run_miner(
+        channel: Receiver<MinerMessage>,
+        parent: Option<Block>,
+        world: World,
+        options: MinerOptions,
+    ) {
+        let mut options = options;
         let mut transactions: Vec<Transaction> = Vec::new();
         let mut parent = parent;
-        let mut block = Block::new(parent.as_ref(), &transactions, &miner);
+        let mut world = world;
+        let mut block =
+            BlockChain::block_template(parent.as_ref(), &world, &transactions, &options);
         println!("Miner started");
         loop {
-            match channel.try_recv() {
-                Ok(message) => match message {
-                    MinerMessage::NewTransaction(transaction, mut world) => {
-                        println!("\nMiner got transaction: {}", transaction);
-                        for t in &transactions {
-                            world.update_on_transaction(&t);
-                        }
-                        if let Ok(()) = transaction.is_valid(&world) {
-                            transactions.push(transaction);
-                            block = Block::new(parent.as_ref(), &transactions, &miner);
-                        }
+            // Block on the channel while idle so a stopped miner doesn't spin.
+            let message = if block.is_some() {
+                match channel.try_recv() {
+                    Ok(message) => Some(message),
+                    Err(mpsc::TryRecvError::Empty) => None,
+                    Err(mpsc::TryRecvError::Disconnected) => {
+                        println!("Miner stopped");
+                        break;
                     }
-                    MinerMessage::NewBlock(new_block) => {
+                }
+            } else {
+                match channel.recv() {
+                    Ok(message) => Some(message),
+                    Err(_) => {
+                        println!("Miner stopped");
+                        break;
+                    }
+                }
+            };
+
+            if let Some(message) = message {
+                match message {
+                    MinerMessage::Transactions(pending) => {
+                        println!("\nMiner got {} pending transactions", pending.len());
+                        transactions = pending;
+                    }
+                    MinerMessage::NewBlock(new_block, new_world) => {
                         println!(
                             "\nBlock {} mined by: {}",
                             new_block.index,
@@ -123,8 +274,20 @@ impl BlockChain {
                                 transactions.remove(index);
                             }
                         }
-                        parent = Some(new_block);
-                        block = Block::new(parent.as_ref(), &transactions, &miner);
+                        parent = Some(*new_block);
+                        world = new_world;
+                    }
+                    MinerMessage::Start => {
+                        println!("\nMining enabled");
+                        options.enabled = true;
+                    }
+                    MinerMessage::Stop => {
+                        println!("\nMining disabled");
+                        options.enabled = false;
+                    }
+                    MinerMessage::SetPayout(address) => {
+                        println!("\nPayout address set to {}", keys::format_address(&address));
+                        options.payout = Some(address);
                     } // MinerMessage::RemoveTransaction(from, index) => {
                       //     println!(
                       //         "\nRemoving transaction {} from {} ",
@@ -132,34 +295,68 @@ impl BlockChain {
                       //         keys::format_address(&from)
                       //     );
                       // }
-                },
-                Err(err) => match err {
-                    mpsc::TryRecvError::Empty => {}
-                    mpsc::TryRecvError::Disconnected => {
-                        println!("Miner stopped");
-                        break;
-                    }
-                },
+                }
+                block =
+                    BlockChain::block_template(parent.as_ref(), &world, &transactions, &options);
             }
 
-            let successfull = block.mine(1000);
+            let candidate = match block {
+                Some(ref mut candidate) => candidate,
+                None => continue,
+            };
+
+            let successfull = candidate.mine_parallel(options.threads, 1000);
             if successfull {
                 println!(
                     "\nBlock mined: {:?}. Transactions: {:?}",
-                    block.index,
-                    block.transactions.len()
+                    candidate.index,
+                    candidate.transactions.len()
                 );
                 let nodes = load_nodes();
                 for node in nodes {
                     let client = BlockchainClient::new(&node);
-                    let response = client.send(ServerNetworkMessage::BroadcastBlock(block.clone()));
+                    let response =
+                        client.send(ServerNetworkMessage::BroadcastBlock(candidate.clone()));
                     println!("Broadcast block to {}. Response: {:?}", node, response);
                 }
-                transactions.clear();
+                transactions.retain(|t| !candidate.transactions.contains(t));
             }
         }
     }
 
+    fn block_template(
+        parent: Option<&Block>,
+        world: &World,
+        transactions: &[Transaction],
+        options: &MinerOptions,
+    ) -> Option<Block> {
+        let payout = match options.payout {
+            Some(ref payout) if options.enabled => payout,
+            _ => return None,
+        };
+
+        // Transactions that can't be mined at this height wait in the pool. Anything depending
+        // on them, like later transactions of the same sender or payments funded by them, is
+        // invalid without them and waits too.
+        let height = parent.map_or(1, |b| b.index + 1);
+        let mut state = world.clone();
+        let ready = transactions
+            .iter()
+            .filter(|t| {
+                if t.is_valid_at(height)
+                    .and_then(|()| t.is_valid(&state))
+                    .is_err()
+                {
+                    return false;
+                }
+                state.update_on_transaction(t);
+                true
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        Some(Block::new(parent, world, &ready, payout))
+    }
+
     pub fn handle_message(&mut self, message: ServerNetworkMessage) -> ClientNetworkMessage {
         match message {
             ServerNetworkMessage::AccountState(address) => ClientNetworkMessage::AccountState(
@@ -181,31 +378,201 @@ impl BlockChain {
                     let chain_after = self.get_chain();
 
                     if chain_after.len() > chain.len() {
+                        let height = chain_after.last().map_or(1, |b| b.index + 1);
+                        let world = World::from_chain(&chain_after);
+                        self.mempool.update(&world, height);
                         if let Some(ref miner) = self.miner {
-                            miner.send(MinerMessage::NewBlock(block)).unwrap();
+                            miner
+                                .send(MinerMessage::NewBlock(Box::new(block), world))
+                                .unwrap();
+                            miner
+                                .send(MinerMessage::Transactions(
+                                    self.mempool.transactions().to_vec(),
+                                ))
+                                .unwrap();
                         }
                     }
 
-                    self.save();
-                    ClientNetworkMessage::Ack
+                    match self.save() {
+                        Ok(()) => ClientNetworkMessage::Ack,
+                        Err(err) => ClientNetworkMessage::Error(err),
+                    }
                 }
                 Err(err) => ClientNetworkMessage::Error(err),
             },
+            ServerNetworkMessage::GetSupply => {
+                let height = self.get_chain().last().map_or(0, |b| b.index);
+                ClientNetworkMessage::Supply {
+                    height,
+                    issued: issued_supply(height),
+                    max: MAX_SUPPLY,
+                }
+            }
+            ServerNetworkMessage::GetTransaction(id) => {
+                ClientNetworkMessage::TransactionStatus(self.transaction_status(&id))
+            }
+            ServerNetworkMessage::GetPending(address) => {
+                ClientNetworkMessage::Pending(self.mempool.pending(&address))
+            }
+            ServerNetworkMessage::GetHistory(address) => {
+                ClientNetworkMessage::History(self.history(&address))
+            }
+            ServerNetworkMessage::GetAccountProof(address) => {
+                ClientNetworkMessage::AccountProof(self.account_proof(&address).map(Box::new))
+            }
+            ServerNetworkMessage::GetChainInfo => {
+                let chain = self.get_chain();
+                let height = chain.last().map_or(0, |b| b.index);
+                ClientNetworkMessage::ChainInfo {
+                    height,
+                    tip: chain.last().map_or([0u8; 32], |b| b.get_hash()),
+                    difficulty: chain.last().map_or(DIFFICULTY_BITS, |b| b.difficulty),
+                    issued: issued_supply(height),
+                    max: MAX_SUPPLY,
+                }
+            }
+            ServerNetworkMessage::GetBlock(id) => {
+                ClientNetworkMessage::Block(self.get_block(id).map(Box::new))
+            }
+            ServerNetworkMessage::GetAddressInfo { address, recent } => {
+                ClientNetworkMessage::AddressInfo(self.address_info(&address, recent as usize))
+            }
+            ServerNetworkMessage::GetHeaders => {
+                ClientNetworkMessage::Headers(self.get_chain().iter().map(|b| b.header()).collect())
+            }
+            ServerNetworkMessage::GetTransactionProof(id) => {
+                ClientNetworkMessage::TransactionProof(self.transaction_proof(&id).map(Box::new))
+            }
+            ServerNetworkMessage::StartMining => match self.payout {
+                Some(_) => self.send_to_miner(MinerMessage::Start),
+                None => ClientNetworkMessage::Error(Error::Config(
+                    "No payout address set. Set one with `client miner payout <address>`"
+                        .to_string(),
+                )),
+            },
+            ServerNetworkMessage::StopMining => self.send_to_miner(MinerMessage::Stop),
+            ServerNetworkMessage::SetPayoutAddress(address) => {
+                let response = self.send_to_miner(MinerMessage::SetPayout(address));
+                if let ClientNetworkMessage::Ack = response {
+                    self.payout = Some(address);
+                }
+                response
+            }
         }
     }
 
-    fn submit_transaction(&mut self, transaction: Transaction) -> Result<(), String> {
-        let world = World::from_chain(&self.get_chain());
-        transaction.is_valid(&world)?;
+    fn send_to_miner(&self, message: MinerMessage) -> ClientNetworkMessage {
         match self.miner {
-            Some(ref channel) => channel
-                .send(MinerMessage::NewTransaction(transaction, world))
-                .unwrap(),
-            None => return Err("Transaction channel not initialized".to_string()),
+            Some(ref channel) => {
+                channel.send(message).unwrap();
+                ClientNetworkMessage::Ack
+            }
+            None => ClientNetworkMessage::Error(Error::Network(
+                "Miner channel not initialized".to_string(),
+            )),
+        }
+    }
+
+    fn submit_transaction(&mut self, transaction: Transaction) -> Result<()> {
+        let chain = self.get_chain();
+        let height = chain.last().map_or(1, |b| b.index + 1);
+        // Time-locked transactions wait in the miner's pool, expired ones are turned away.
+        if let Err(err @ Error::Validation(ValidationError::Expired { .. })) =
+            transaction.is_valid_at(height)
+        {
+            return Err(err);
         }
+        let channel = match self.miner {
+            Some(ref channel) => channel,
+            None => {
+                return Err(Error::Network(
+                    "Transaction channel not initialized".to_string(),
+                ))
+            }
+        };
+        self.mempool
+            .insert(transaction, &World::from_chain(&chain), height)?;
+        channel
+            .send(MinerMessage::Transactions(
+                self.mempool.transactions().to_vec(),
+            ))
+            .unwrap();
         Ok(())
     }
 
+    fn transaction_status(&self, id: &Hash) -> TransactionStatus {
+        let chain = self.get_chain();
+        let tip = chain.last().map_or(0, |b| b.index);
+        for block in &chain {
+            if block.transactions.iter().any(|t| t.id() == *id) {
+                return TransactionStatus::Confirmed {
+                    height: block.index,
+                    confirmations: tip - block.index + 1,
+                };
+            }
+        }
+        if self.mempool.contains(id) {
+            return TransactionStatus::Pending;
+        }
+        TransactionStatus::Unknown
+    }
+
+    /// The state of `address` at the chain tip and its proof against the tip's state root.
+    fn account_proof(&self, address: &Address) -> Option<AccountProof> {
+        let chain = self.get_chain();
+        let tip = chain.last()?;
+        let world = World::from_chain(&chain);
+        Some(AccountProof {
+            state: world.get_account_state(address),
+            height: tip.index,
+            block_hash: tip.get_hash(),
+            proof: world.prove(address),
+        })
+    }
+
+    fn transaction_proof(&self, id: &Hash) -> Option<InclusionProof> {
+        self.get_chain().iter().find_map(|block| {
+            let position = block.transactions.iter().position(|t| t.id() == *id)?;
+            block.prove(position)
+        })
+    }
+
+    fn get_block(&self, id: BlockId) -> Option<Block> {
+        match id {
+            BlockId::Hash(hash) => self.blocks.get(&hash).cloned(),
+            BlockId::Height(height) => self.get_chain().into_iter().find(|b| b.index == height),
+        }
+    }
+
+    fn address_info(&self, address: &Address, recent: usize) -> AddressInfo {
+        let mut history = self.history(address);
+        let recent = history.split_off(history.len().saturating_sub(recent));
+        AddressInfo {
+            state: World::from_chain(&self.get_chain()).get_account_state(address),
+            pending: self.mempool.pending(address),
+            recent,
+        }
+    }
+
+    fn history(&self, address: &Address) -> Vec<HistoryEntry> {
+        let mut history = Vec::new();
+        for block in self.get_chain() {
+            for transaction in &block.transactions {
+                let received = transaction
+                    .outputs()
+                    .iter()
+                    .any(|output| output.recipient == *address);
+                if transaction.sender == *address || received {
+                    history.push(HistoryEntry {
+                        height: block.index,
+                        transaction: transaction.clone(),
+                    });
+                }
+            }
+        }
+        history
+    }
+
     pub fn get_chain(&self) -> Vec<Block> {
         let mut leaves: HashSet<Hash> = self.blocks.clone().into_keys().collect();
 
@@ -268,13 +635,41 @@ impl BlockChain {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct AccountState {
     pub address: Address,
     pub balance: u128,
     pub transaction_index: u128,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum TransactionStatus {
+    /// Waiting in the node's mempool.
+    Pending,
+    /// Included in the block at `height`, which has `confirmations - 1` blocks on top of it.
+    Confirmed {
+        height: u128,
+        confirmations: u128,
+    },
+    Unknown,
+}
+
+/// A confirmed transaction and the height of the block that included it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HistoryEntry {
+    pub height: u128,
+    pub transaction: Transaction,
+}
+
+/// What the node knows about an address.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AddressInfo {
+    pub state: AccountState,
+    pub pending: Vec<Transaction>,
+    /// The last confirmed transactions, oldest first.
+    pub recent: Vec<HistoryEntry>,
+}
+
 impl AccountState {
     pub fn new(address: Address) -> AccountState {
         AccountState {
@@ -286,17 +681,20 @@ impl AccountState {
 
     pub fn update_on_block(&mut self, block: &Block) {
         if block.miner == self.address {
-            self.balance += block.reward;
+            self.balance += block.reward + block.fees();
         }
     }
 
     pub fn update_on_transaction(&mut self, transaction: &Transaction) {
         if transaction.sender == self.address {
-            self.balance -= transaction.amount;
+            // Validation already rejected totals that overflow.
+            self.balance -= transaction.total_amount().unwrap_or(0) + transaction.fee;
             self.transaction_index += 1;
         }
-        if transaction.recipient == self.address {
-            self.balance += transaction.amount;
+        for output in transaction.outputs() {
+            if output.recipient == self.address {
+                self.balance += output.amount;
+            }
         }
     }
 }
@@ -340,16 +738,44 @@ impl World {
     }
 
     pub fn update_on_transaction(&mut self, transaction: &Transaction) {
-        let sender = self.get_account_state_mut(&transaction.sender);
-        sender.update_on_transaction(transaction);
-        let recipient = self.get_account_state_mut(&transaction.recipient);
-        recipient.update_on_transaction(transaction);
+        // Every account the transaction touches is updated exactly once, so a sender paying
+        // itself or a recipient listed twice in a batch is credited correctly.
+        let mut addresses = vec![transaction.sender];
+        for output in transaction.outputs() {
+            if !addresses.contains(&output.recipient) {
+                addresses.push(output.recipient);
+            }
+        }
+        for address in addresses {
+            self.get_account_state_mut(&address)
+                .update_on_transaction(transaction);
+        }
     }
     pub fn update_on_block(&mut self, block: &Block) {
         let miner = self.get_account_state_mut(&block.miner);
         miner.update_on_block(block);
     }
 
+    /// Root of the sparse Merkle tree over all account states, see [`merkle::state_root`].
+    pub fn root(&self) -> Hash {
+        merkle::state_root(&self.leaves())
+    }
+
+    /// Proves the state of `address`, even if the account was never used.
+    pub fn prove(&self, address: &Address) -> merkle::StateProof {
+        merkle::prove_state(&self.leaves(), address)
+    }
+
+    fn leaves(&self) -> Vec<(Address, Hash)> {
+        let mut leaves = self
+            .accounts
+            .values()
+            .map(|state| (state.address, merkle::account_leaf(state)))
+            .collect::<Vec<_>>();
+        leaves.sort();
+        leaves
+    }
+
     pub fn from_chain(chain: &Vec<Block>) -> World {
         let mut world = World::new();
         for block in chain {
@@ -367,3 +793,180 @@ impl Default for World {
         World::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf, process};
+
+    use openssl::pkey::{PKey, Private};
+
+    use super::*;
+    use crate::types::COIN;
+
+    fn keypair() -> (PKey<Private>, Address) {
+        let key = PKey::generate_ed25519().unwrap();
+        let address = keys::keypair_to_address(&key).unwrap();
+        (key, address)
+    }
+
+    fn payment(
+        key: &PKey<Private>,
+        recipient: Address,
+        amount: u128,
+        index: u128,
+        not_before: Option<u128>,
+    ) -> Transaction {
+        let sender = keys::keypair_to_address(key).unwrap();
+        let mut transaction = Transaction::new(sender, recipient, amount, index);
+        transaction.not_before = not_before;
+        transaction.sign(key).unwrap();
+        transaction
+    }
+
+    #[test]
+    fn block_template_leaves_out_transactions_that_depend_on_waiting_ones() {
+        config::init_for_tests();
+        let (alice_key, alice) = keypair();
+        let (bob_key, bob) = keypair();
+        let (_, carol) = keypair();
+        let genesis = Block::new(None, &World::new(), &[], &alice);
+        let mut world = World::new();
+        world.update_on_block(&genesis);
+
+        let to_bob = payment(&alice_key, bob, 10 * COIN, 1, None);
+        // Time-locked, so it waits, and Bob's next transaction can't be mined without it.
+        let locked = payment(&bob_key, carol, 5 * COIN, 1, Some(10));
+        let after_locked = payment(&bob_key, carol, COIN, 2, None);
+        let overdraft = payment(&alice_key, carol, 100 * COIN, 2, None);
+        let to_carol = payment(&alice_key, carol, COIN, 2, None);
+        let options = MinerOptions {
+            enabled: true,
+            threads: 1,
+            payout: Some(alice),
+        };
+
+        let template = BlockChain::block_template(
+            Some(&genesis),
+            &world,
+            &[
+                to_bob.clone(),
+                locked,
+                after_locked,
+                overdraft,
+                to_carol.clone(),
+            ],
+            &options,
+        )
+        .unwrap();
+        assert_eq!(template.transactions, vec![to_bob, to_carol]);
+
+        let mut state = world.clone();
+        for transaction in &template.transactions {
+            state.update_on_transaction(transaction);
+        }
+        state.update_on_block(&template);
+        assert_eq!(template.state_root, state.root());
+    }
+
+    #[test]
+    fn block_template_needs_a_payout_address() {
+        let options = MinerOptions {
+            enabled: true,
+            threads: 1,
+            payout: None,
+        };
+        assert!(BlockChain::block_template(None, &World::new(), &[], &options).is_none());
+    }
+
+    /// A chain of `count` mined blocks. The second one pays `COIN` from the first miner to the
+    /// second.
+    fn mined_chain(count: usize) -> BlockChain {
+        config::init_for_tests();
+        let (key, miner) = keypair();
+        let (_, recipient) = keypair();
+        let mut chain = BlockChain::new();
+        let mut world = World::new();
+        let mut parent: Option<Block> = None;
+        for height in 1..=count {
+            let transactions = match height {
+                2 => vec![payment(&key, recipient, COIN, 1, None)],
+                _ => Vec::new(),
+            };
+            let mut block = Block::new(parent.as_ref(), &world, &transactions, &miner);
+            assert!(block.mine(u128::MAX));
+            block.apply(parent.as_ref(), &mut world).unwrap();
+            chain.blocks.insert(block.get_hash(), block.clone());
+            parent = Some(block);
+        }
+        chain
+    }
+
+    fn export_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("zenchain-{}-{}.bin", name, process::id()))
+    }
+
+    #[test]
+    fn export_import_round_trip() {
+        let chain = mined_chain(3);
+        let path = export_path("round-trip");
+        assert_eq!(chain.export_chain(&path).unwrap(), 3);
+
+        let mut imported = BlockChain::new();
+        assert_eq!(imported.import_chain(&path).unwrap(), 3);
+        assert_eq!(imported.blocks, chain.blocks);
+        // Blocks the chain already has are skipped.
+        assert_eq!(imported.import_chain(&path).unwrap(), 0);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn import_rejects_a_block_without_parent() {
+        let chain = mined_chain(2);
+        let path = export_path("orphan");
+        chain.export_chain(&path).unwrap();
+        // Drop the first block so the file starts with its child.
+        let data = fs::read(&path).unwrap();
+        let first_len = u32::from_le_bytes(data[12..16].try_into().unwrap()) as usize;
+        let mut orphaned = data[..12].to_vec();
+        orphaned.extend_from_slice(&data[16 + first_len..]);
+        fs::write(&path, orphaned).unwrap();
+
+        let mut imported = BlockChain::new();
+        assert_eq!(
+            imported.import_chain(&path),
+            Err(ConsensusError::InvalidBlock {
+                index: 2,
+                reason: Box::new(ConsensusError::ParentNotFound)
+            }
+            .into())
+        );
+        assert!(imported.blocks.is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn import_rejects_a_truncated_file() {
+        let chain = mined_chain(2);
+        let path = export_path("truncated");
+        chain.export_chain(&path).unwrap();
+        let data = fs::read(&path).unwrap();
+        fs::write(&path, &data[..data.len() - 1]).unwrap();
+
+        let mut imported = BlockChain::new();
+        assert!(matches!(
+            imported.import_chain(&path),
+            Err(Error::Storage(msg)) if msg.contains("ends in the middle of a block")
+        ));
+        // All-or-nothing is up to the caller, which doesn't save after an error.
+        assert_eq!(imported.blocks.len(), 1);
+
+        let mut oversized = data[..12].to_vec();
+        oversized.extend_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(&path, oversized).unwrap();
+        assert!(matches!(
+            BlockChain::new().import_chain(&path),
+            Err(Error::Storage(msg)) if msg.contains("more than the maximum")
+        ));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -4,8 +4,12 @@ use std::{
 };
 
 use crate::{
-    blockchain::AccountState,
-    types::{Address, ClientNetworkMessage, ServerNetworkMessage},
+    block::{Block, BlockHeader},
+    blockchain::{AccountState, AddressInfo, HistoryEntry, TransactionStatus},
+    error::{Error, Result},
+    merkle::{AccountProof, InclusionProof},
+    transaction::Transaction,
+    types::{Address, BlockId, ClientNetworkMessage, Hash, ServerNetworkMessage},
 };
 
 pub struct BlockchainClient {
@@ -19,41 +23,111 @@ impl BlockchainClient {
         }
     }
 
-    fn connect(&self) -> Result<TcpStream, String> {
-        let stream = TcpStream::connect(&self.address).map_err(|e| e.to_string())?;
+    fn connect(&self) -> Result<TcpStream> {
+        let stream = TcpStream::connect(&self.address).map_err(network_error)?;
         return Ok(stream);
     }
 
-    pub fn account_state(&self, address: Address) -> Result<AccountState, String> {
-        let mut stream = self.connect()?;
-        BlockchainClient::write_message(&mut stream, ServerNetworkMessage::AccountState(address));
-        match BlockchainClient::read_message(&mut stream) {
+    pub fn account_state(&self, address: Address) -> Result<AccountState> {
+        match self.send(ServerNetworkMessage::AccountState(address))? {
             ClientNetworkMessage::AccountState(state) => Ok(state),
-            msg => panic!("Unexpected message: {:?}", msg),
+            ClientNetworkMessage::Error(err) => Err(err),
+            msg => Err(Error::Network(format!("Unexpected message: {:?}", msg))),
+        }
+    }
+
+    /// Confirmed transactions sent or received by `address`, oldest first.
+    pub fn history(&self, address: Address) -> Result<Vec<HistoryEntry>> {
+        match self.send(ServerNetworkMessage::GetHistory(address))? {
+            ClientNetworkMessage::History(history) => Ok(history),
+            ClientNetworkMessage::Error(err) => Err(err),
+            msg => Err(Error::Network(format!("Unexpected message: {:?}", msg))),
+        }
+    }
+
+    /// Transactions sent by `address` that are waiting to be mined.
+    pub fn pending(&self, address: Address) -> Result<Vec<Transaction>> {
+        match self.send(ServerNetworkMessage::GetPending(address))? {
+            ClientNetworkMessage::Pending(pending) => Ok(pending),
+            ClientNetworkMessage::Error(err) => Err(err),
+            msg => Err(Error::Network(format!("Unexpected message: {:?}", msg))),
         }
     }
 
-    pub fn send(&self, message: ServerNetworkMessage) -> Result<ClientNetworkMessage, String> {
+    pub fn transaction_status(&self, id: Hash) -> Result<TransactionStatus> {
+        match self.send(ServerNetworkMessage::GetTransaction(id))? {
+            ClientNetworkMessage::TransactionStatus(status) => Ok(status),
+            ClientNetworkMessage::Error(err) => Err(err),
+            msg => Err(Error::Network(format!("Unexpected message: {:?}", msg))),
+        }
+    }
+
+    pub fn block(&self, id: BlockId) -> Result<Option<Block>> {
+        match self.send(ServerNetworkMessage::GetBlock(id))? {
+            ClientNetworkMessage::Block(block) => Ok(block.map(|block| *block)),
+            ClientNetworkMessage::Error(err) => Err(err),
+            msg => Err(Error::Network(format!("Unexpected message: {:?}", msg))),
+        }
+    }
+
+    pub fn address_info(&self, address: Address, recent: u32) -> Result<AddressInfo> {
+        match self.send(ServerNetworkMessage::GetAddressInfo { address, recent })? {
+            ClientNetworkMessage::AddressInfo(info) => Ok(info),
+            ClientNetworkMessage::Error(err) => Err(err),
+            msg => Err(Error::Network(format!("Unexpected message: {:?}", msg))),
+        }
+    }
+
+    pub fn account_proof(&self, address: Address) -> Result<Option<AccountProof>> {
+        match self.send(ServerNetworkMessage::GetAccountProof(address))? {
+            ClientNetworkMessage::AccountProof(proof) => Ok(proof.map(|proof| *proof)),
+            ClientNetworkMessage::Error(err) => Err(err),
+            msg => Err(Error::Network(format!("Unexpected message: {:?}", msg))),
+        }
+    }
+
+    pub fn headers(&self) -> Result<Vec<BlockHeader>> {
+        match self.send(ServerNetworkMessage::GetHeaders)? {
+            ClientNetworkMessage::Headers(headers) => Ok(headers),
+            ClientNetworkMessage::Error(err) => Err(err),
+            msg => Err(Error::Network(format!("Unexpected message: {:?}", msg))),
+        }
+    }
+
+    pub fn transaction_proof(&self, id: Hash) -> Result<Option<InclusionProof>> {
+        match self.send(ServerNetworkMessage::GetTransactionProof(id))? {
+            ClientNetworkMessage::TransactionProof(proof) => Ok(proof.map(|proof| *proof)),
+            ClientNetworkMessage::Error(err) => Err(err),
+            msg => Err(Error::Network(format!("Unexpected message: {:?}", msg))),
+        }
+    }
+
+    pub fn send(&self, message: ServerNetworkMessage) -> Result<ClientNetworkMessage> {
         let mut stream = self.connect()?;
-        BlockchainClient::write_message(&mut stream, message);
-        Ok(BlockchainClient::read_message(&mut stream))
+        BlockchainClient::write_message(&mut stream, message)?;
+        BlockchainClient::read_message(&mut stream)
     }
 
-    fn read_message(stream: &mut TcpStream) -> ClientNetworkMessage {
+    fn read_message(stream: &mut TcpStream) -> Result<ClientNetworkMessage> {
         let mut len_buffer = [0u8; 4];
-        stream.read_exact(&mut len_buffer).unwrap();
+        stream.read_exact(&mut len_buffer).map_err(network_error)?;
         let len = u32::from_le_bytes(len_buffer);
         let mut buffer: Vec<u8> = vec![0u8; len as usize];
-        stream.read_exact(&mut buffer).unwrap();
-        let message = bincode::deserialize::<ClientNetworkMessage>(&buffer).unwrap();
-        return message;
+        stream.read_exact(&mut buffer).map_err(network_error)?;
+        let message = bincode::deserialize::<ClientNetworkMessage>(&buffer)
+            .map_err(|e| Error::Network(e.to_string()))?;
+        return Ok(message);
     }
 
-    fn write_message(stream: &mut TcpStream, message: ServerNetworkMessage) {
-        let buffer = bincode::serialize(&message).unwrap();
+    fn write_message(stream: &mut TcpStream, message: ServerNetworkMessage) -> Result<()> {
+        let buffer = bincode::serialize(&message).map_err(|e| Error::Network(e.to_string()))?;
         let len_bytes = (buffer.len() as u32).to_le_bytes();
-        stream.write(&len_bytes).unwrap();
-        stream.write(&buffer).unwrap();
-        stream.flush().unwrap();
+        stream.write_all(&len_bytes).map_err(network_error)?;
+        stream.write_all(&buffer).map_err(network_error)?;
+        stream.flush().map_err(network_error)
     }
 }
+
+fn network_error(err: std::io::Error) -> Error {
+    Error::Network(err.to_string())
+}
@@ -0,0 +1,171 @@
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use serde::Deserialize;
+
+use crate::error::{Error, Result};
+
+pub const CONFIG_FILE: &str = "zenchain.toml";
+pub const DEFAULT_PORT: u16 = 8888;
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Settings shared by the node and the client. Every field has a default so a config file is
+/// optional; see [`Config::load`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub network: Network,
+    pub data_dir: PathBuf,
+    /// IPv4 or IPv6 address the node listens on, see [`Config::listen_address`].
+    pub bind_address: IpAddr,
+    pub port: u16,
+    /// Node the client talks to.
+    pub node: String,
+    pub peers: Vec<String>,
+    /// File in the data directory listing the peers, used if `peers` isn't set.
+    pub nodes_file: PathBuf,
+    pub mining: MiningConfig,
+}
+
+/// The zenchain network to use. Each has its own chain id and address prefix, so transactions
+/// and addresses of one network are rejected on the others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    Mainnet,
+    Testnet,
+    Regtest,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MiningConfig {
+    pub enabled: bool,
+    pub threads: usize,
+    pub key: Option<String>,
+    pub payout_address: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            network: Network::Mainnet,
+            data_dir: PathBuf::from("."),
+            bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: DEFAULT_PORT,
+            node: format!("localhost:{}", DEFAULT_PORT),
+            peers: Vec::new(),
+            nodes_file: PathBuf::from("nodes.txt"),
+            mining: MiningConfig::default(),
+        }
+    }
+}
+
+impl Network {
+    /// Signed into every transaction of the network.
+    pub fn chain_id(self) -> u32 {
+        match self {
+            Network::Mainnet => 1,
+            Network::Testnet => 2,
+            Network::Regtest => 3,
+        }
+    }
+
+    /// Human readable prefix of addresses on the network.
+    pub fn address_prefix(self) -> &'static str {
+        match self {
+            Network::Mainnet => "zen",
+            Network::Testnet => "tzen",
+            Network::Regtest => "rzen",
+        }
+    }
+}
+
+impl Default for MiningConfig {
+    fn default() -> Self {
+        MiningConfig {
+            enabled: true,
+            threads: 1,
+            key: None,
+            payout_address: None,
+        }
+    }
+}
+
+impl Config {
+    /// Reads `config_file`, or `zenchain.toml` in the data directory if none is given.
+    ///
+    /// `data_dir` overrides the `data_dir` setting of the file. Peers come from the `peers`
+    /// setting, falling back to the `nodes_file` (default `nodes.txt`) in the data directory.
+    pub fn load(config_file: Option<&Path>, data_dir: Option<&Path>) -> Result<Config> {
+        let default_dir = data_dir.unwrap_or_else(|| Path::new("."));
+        let (path, required) = match config_file {
+            Some(path) => (path.to_path_buf(), true),
+            None => (default_dir.join(CONFIG_FILE), false),
+        };
+
+        let table = if path.exists() || required {
+            let text = fs::read_to_string(&path)
+                .map_err(|e| Error::Config(format!("{}: {}", path.display(), e)))?;
+            text.parse::<toml::Table>()
+                .map_err(|e| Error::Config(format!("{}: {}", path.display(), e)))?
+        } else {
+            toml::Table::new()
+        };
+        let has_peers = table.contains_key("peers");
+        let mut config: Config = table
+            .try_into()
+            .map_err(|e| Error::Config(format!("{}: {}", path.display(), e)))?;
+        if let Some(data_dir) = data_dir {
+            config.data_dir = data_dir.to_path_buf();
+        }
+
+        if !has_peers {
+            let path = config.data_dir.join(&config.nodes_file);
+            if let Ok(nodes) = fs::read_to_string(path) {
+                config.peers = nodes
+                    .lines()
+                    .map(|line| line.trim().to_string())
+                    .filter(|line| !line.is_empty())
+                    .collect();
+            }
+        }
+        Ok(config)
+    }
+
+    /// The address the node listens on when it uses `port`.
+    pub fn listen_address(&self, port: u16) -> SocketAddr {
+        SocketAddr::new(self.bind_address, port)
+    }
+
+    pub fn keys_dir(&self) -> PathBuf {
+        self.data_dir.join("keys")
+    }
+
+    pub fn chain_file(&self) -> PathBuf {
+        self.data_dir.join("zenchain-data.bin")
+    }
+}
+
+/// Makes `config` the process wide configuration. Must be called before anything reads it.
+pub fn init(config: Config) {
+    if CONFIG.set(config).is_err() {
+        panic!("Config already initialized");
+    }
+}
+
+/// Installs the default configuration for unit tests, which all share one process.
+#[cfg(test)]
+pub fn init_for_tests() {
+    CONFIG.get_or_init(Config::default);
+}
+
+/// The process wide configuration. Panics if [`init`] was never called.
+pub fn get() -> &'static Config {
+    CONFIG.get().expect("Config not initialized")
+}
@@ -0,0 +1,253 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    Validation(ValidationError),
+    Consensus(ConsensusError),
+    Network(String),
+    Storage(String),
+    Config(String),
+    Key(KeyError),
+}
+
+/// A transaction (or user supplied value) is malformed or not allowed by the current state.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    InvalidSignature,
+    InsufficientBalance {
+        balance: u128,
+        amount: u128,
+    },
+    InvalidTransactionIndex {
+        expected: u128,
+        actual: u128,
+    },
+    InvalidAmount(String),
+    InvalidChainId {
+        expected: u32,
+        actual: u32,
+    },
+    TransactionMismatch,
+    TimeLocked {
+        not_before: u128,
+        height: u128,
+    },
+    Expired {
+        valid_until: u128,
+        height: u128,
+    },
+    InvalidOutputs(String),
+    MemoTooLong {
+        length: usize,
+        max: usize,
+    },
+    InvalidScript(String),
+    ReplacementFeeTooLow {
+        pending_fee: u128,
+        fee: u128,
+        minimum: u128,
+    },
+    MempoolFull {
+        lowest_fee: u128,
+    },
+    InvalidHash(String),
+}
+
+/// A block breaks the chain rules.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ConsensusError {
+    InvalidDifficulty {
+        expected: u32,
+        actual: u32,
+    },
+    InvalidReward {
+        expected: u128,
+        actual: u128,
+    },
+    ParentNotFound,
+    InvalidIndex {
+        expected: u128,
+        actual: u128,
+    },
+    InvalidHash,
+    InvalidTransaction {
+        position: usize,
+        reason: ValidationError,
+    },
+    InvalidMerkleRoot,
+    InvalidInclusionProof,
+    InvalidStateRoot,
+    InvalidAccountProof,
+    /// The block at `index` breaks a rule, for errors that don't already say which block.
+    InvalidBlock {
+        index: u128,
+        reason: Box<ConsensusError>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum KeyError {
+    NoDefaultKeypair,
+    KeypairNotFound(String),
+    InvalidKey(String),
+    InvalidAddress(String),
+    InvalidPassphrase(String),
+    InvalidMnemonic(String),
+    KeypairExists(String),
+}
+
+impl std::error::Error for Error {}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Validation(err) => write!(f, "{}", err),
+            Error::Consensus(err) => write!(f, "{}", err),
+            Error::Network(msg) => write!(f, "Network error: {}", msg),
+            Error::Storage(msg) => write!(f, "Storage error: {}", msg),
+            Error::Config(msg) => write!(f, "Config error: {}", msg),
+            Error::Key(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationError::InvalidSignature => write!(f, "Invalid signature"),
+            ValidationError::InsufficientBalance { balance, amount } => write!(
+                f,
+                "Insufficient balance. Has {} but needs {}",
+                balance, amount
+            ),
+            ValidationError::InvalidTransactionIndex { expected, actual } => write!(
+                f,
+                "Invalid transaction index. Should be {}, but is {}",
+                expected, actual
+            ),
+            ValidationError::InvalidAmount(msg) => write!(f, "Invalid amount: {}", msg),
+            ValidationError::InvalidChainId { expected, actual } => write!(
+                f,
+                "Transaction is for chain {}, but this is chain {}",
+                actual, expected
+            ),
+            ValidationError::TransactionMismatch => {
+                write!(f, "Transactions don't match and can't be combined")
+            }
+            ValidationError::TimeLocked { not_before, height } => write!(
+                f,
+                "Transaction can't be included before block {}, this is block {}",
+                not_before, height
+            ),
+            ValidationError::Expired {
+                valid_until,
+                height,
+            } => write!(
+                f,
+                "Transaction expired at block {}, this is block {}",
+                valid_until, height
+            ),
+            ValidationError::InvalidOutputs(msg) => write!(f, "Invalid outputs: {}", msg),
+            ValidationError::MemoTooLong { length, max } => {
+                write!(f, "Memo is {} bytes, at most {} are allowed", length, max)
+            }
+            ValidationError::InvalidScript(msg) => write!(f, "Invalid script: {}", msg),
+            ValidationError::ReplacementFeeTooLow {
+                pending_fee,
+                fee,
+                minimum,
+            } => write!(
+                f,
+                "Replacement fee {} is too low, the pending transaction pays {} so at least {} is needed",
+                fee, pending_fee, minimum
+            ),
+            ValidationError::MempoolFull { lowest_fee } => write!(
+                f,
+                "Mempool is full, the fee must be higher than {}",
+                lowest_fee
+            ),
+            ValidationError::InvalidHash(msg) => write!(f, "Invalid hash: {}", msg),
+        }
+    }
+}
+
+impl Display for ConsensusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConsensusError::InvalidDifficulty { expected, actual } => write!(
+                f,
+                "Invalid difficulty. Should be {}, but is {}",
+                expected, actual
+            ),
+            ConsensusError::InvalidReward { expected, actual } => write!(
+                f,
+                "Invalid reward. Should be {}, but is {}",
+                expected, actual
+            ),
+            ConsensusError::ParentNotFound => write!(f, "Invalid prev_hash. Parent not found"),
+            ConsensusError::InvalidIndex { expected, actual } => write!(
+                f,
+                "Invalid index. Should be {}, but is {}",
+                expected, actual
+            ),
+            ConsensusError::InvalidHash => write!(f, "Invalid hash. Did you really do the work?"),
+            ConsensusError::InvalidTransaction { position, reason } => {
+                write!(f, "Invalid transaction #{}: {}", position, reason)
+            }
+            ConsensusError::InvalidMerkleRoot => {
+                write!(f, "Merkle root doesn't match the transactions")
+            }
+            ConsensusError::InvalidInclusionProof => {
+                write!(f, "Transaction is not proven to be in the block")
+            }
+            ConsensusError::InvalidStateRoot => {
+                write!(
+                    f,
+                    "State root doesn't match the account states after the block"
+                )
+            }
+            ConsensusError::InvalidAccountProof => {
+                write!(f, "Account state is not proven by the block")
+            }
+            ConsensusError::InvalidBlock { index, reason } => {
+                write!(f, "Invalid block {}: {}", index, reason)
+            }
+        }
+    }
+}
+
+impl Display for KeyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyError::NoDefaultKeypair => write!(f, "No default keypair set"),
+            KeyError::KeypairNotFound(name) => write!(f, "Keypair '{}' does not exist", name),
+            KeyError::InvalidKey(msg) => write!(f, "Invalid key: {}", msg),
+            KeyError::InvalidAddress(msg) => write!(f, "Invalid address: {}", msg),
+            KeyError::InvalidPassphrase(msg) => write!(f, "Invalid passphrase: {}", msg),
+            KeyError::InvalidMnemonic(msg) => write!(f, "Invalid seed phrase: {}", msg),
+            KeyError::KeypairExists(name) => write!(f, "Keypair '{}' already exists", name),
+        }
+    }
+}
+
+impl From<ValidationError> for Error {
+    fn from(err: ValidationError) -> Self {
+        Error::Validation(err)
+    }
+}
+
+impl From<ConsensusError> for Error {
+    fn from(err: ConsensusError) -> Self {
+        Error::Consensus(err)
+    }
+}
+
+impl From<KeyError> for Error {
+    fn from(err: KeyError) -> Self {
+        Error::Key(err)
+    }
+}
@@ -1,38 +1,197 @@
-use std::{fs, path::Path};
+use std::{
+    env,
+    fmt::Display,
+    fs,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    str::FromStr,
+};
 
 use openssl::{
+    base64,
+    bn::BigNumContext,
+    ec::{EcGroup, EcKey, EcPoint, PointConversionForm},
     hash::{hash, MessageDigest},
-    pkey::{HasPublic, Private},
+    nid::Nid,
+    pkcs5::pbkdf2_hmac,
+    pkey::{HasPublic, Id, PKey, Private, Public},
+    rand::rand_bytes,
     rsa::Rsa,
+    sign::{Signer, Verifier},
+    symm::{self, Cipher},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bech32, config,
+    error::{Error, KeyError, Result, ValidationError},
+    mnemonic, script,
+    types::{Address, Hash, PublicKey, COIN, COIN_DECIMALS},
 };
 
-use crate::types::Address;
+/// Unlocks encrypted keys without prompting, e.g. for nodes started by a service manager.
+pub const PASSPHRASE_ENV: &str = "ZENCHAIN_PASSPHRASE";
+/// Passphrase used when generating or re-encrypting keys without prompting.
+pub const NEW_PASSPHRASE_ENV: &str = "ZENCHAIN_NEW_PASSPHRASE";
+/// PEM label of keys encrypted with [`encrypt_secret`].
+const KEYSTORE_PEM_LABEL: &str = "ZENCHAIN ENCRYPTED KEY";
+
+const SEED_ENCRYPTED: u8 = 1;
+/// PBKDF2 iterations for everything encrypted with a passphrase.
+const KDF_ITERATIONS: usize = 200_000;
+const SALT_LEN: usize = 16;
+/// GCM nonce length.
+const IV_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const COMPRESSED_POINT_LEN: usize = 33;
+
+/// Addresses of RSA keys are the hash of the DER public key. This is the original scheme and is
+/// kept so that existing addresses stay valid.
+const ADDRESS_VERSION_LEGACY: u8 = 0;
+/// Addresses of all other key types also commit to the version and key type.
+const ADDRESS_VERSION_TYPED: u8 = 1;
+/// Addresses of multisig accounts, see `MultisigPolicy::address`.
+pub const ADDRESS_VERSION_MULTISIG: u8 = 2;
+/// Addresses of accounts locked by a script, see `Script::address`.
+pub const ADDRESS_VERSION_SCRIPT: u8 = 3;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyType {
+    Rsa2048,
+    Ed25519,
+    Secp256k1,
+}
+
+impl KeyType {
+    pub fn generate(&self) -> Result<PKey<Private>> {
+        let key = match self {
+            KeyType::Rsa2048 => Rsa::generate(2048).and_then(PKey::from_rsa),
+            KeyType::Ed25519 => PKey::generate_ed25519(),
+            KeyType::Secp256k1 => EcGroup::from_curve_name(Nid::SECP256K1)
+                .and_then(|group| EcKey::generate(&group))
+                .and_then(PKey::from_ec_key),
+        };
+        key.map_err(key_error)
+    }
+
+    pub fn of<T: HasPublic>(key: &PKey<T>) -> Result<KeyType> {
+        match key.id() {
+            Id::RSA => {
+                // The modulus size in bytes.
+                let size = key.rsa().map_err(key_error)?.size();
+                if size != 256 {
+                    return Err(KeyError::InvalidKey(format!(
+                        "RSA keys must be 2048 bits, not {}",
+                        size * 8
+                    ))
+                    .into());
+                }
+                Ok(KeyType::Rsa2048)
+            }
+            Id::ED25519 => Ok(KeyType::Ed25519),
+            Id::EC => {
+                let curve = key.ec_key().map_err(key_error)?.group().curve_name();
+                match curve {
+                    Some(Nid::SECP256K1) => Ok(KeyType::Secp256k1),
+                    _ => Err(KeyError::InvalidKey("Unsupported curve".to_string()).into()),
+                }
+            }
+            _ => Err(KeyError::InvalidKey("Unsupported key type".to_string()).into()),
+        }
+    }
+
+    fn tag(&self) -> u8 {
+        match self {
+            KeyType::Rsa2048 => 0,
+            KeyType::Ed25519 => 1,
+            KeyType::Secp256k1 => 2,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Result<KeyType> {
+        match tag {
+            0 => Ok(KeyType::Rsa2048),
+            1 => Ok(KeyType::Ed25519),
+            2 => Ok(KeyType::Secp256k1),
+            _ => Err(KeyError::InvalidKey(format!("Unknown key type {}", tag)).into()),
+        }
+    }
+
+    fn address_version(&self) -> u8 {
+        match self {
+            KeyType::Rsa2048 => ADDRESS_VERSION_LEGACY,
+            KeyType::Ed25519 | KeyType::Secp256k1 => ADDRESS_VERSION_TYPED,
+        }
+    }
+}
+
+impl Display for KeyType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyType::Rsa2048 => write!(f, "rsa2048"),
+            KeyType::Ed25519 => write!(f, "ed25519"),
+            KeyType::Secp256k1 => write!(f, "secp256k1"),
+        }
+    }
+}
+
+impl FromStr for KeyType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<KeyType> {
+        match s.to_lowercase().as_str() {
+            "rsa" | "rsa2048" => Ok(KeyType::Rsa2048),
+            "ed25519" => Ok(KeyType::Ed25519),
+            "secp256k1" => Ok(KeyType::Secp256k1),
+            _ => Err(KeyError::InvalidKey(format!("Unknown key type '{}'", s)).into()),
+        }
+    }
+}
+
+fn storage_error(err: std::io::Error) -> Error {
+    Error::Storage(err.to_string())
+}
+
+fn key_error(err: openssl::error::ErrorStack) -> Error {
+    Error::Key(KeyError::InvalidKey(err.to_string()))
+}
 
-pub fn create_keys_folder() {
-    fs::create_dir_all("./keys/").unwrap();
+fn key_file(name: &str, extension: &str) -> PathBuf {
+    config::get()
+        .keys_dir()
+        .join(format!("{}.{}", name, extension))
 }
 
-pub fn generate_keypair(name: String) {
-    create_keys_folder();
+fn seed_file() -> PathBuf {
+    config::get().keys_dir().join("seed")
+}
 
-    let rsa = Rsa::generate(2048).unwrap();
-    let private_key = rsa.private_key_to_pem().unwrap();
-    let public_key = rsa.public_key_to_pem().unwrap();
+pub fn create_keys_folder() -> Result<()> {
+    fs::create_dir_all(config::get().keys_dir()).map_err(storage_error)
+}
 
-    fs::write(format!("./keys/{}.sk", name), private_key).unwrap();
-    fs::write(format!("./keys/{}.pk", name), public_key).unwrap();
+pub fn generate_keypair(name: String, key_type: KeyType) -> Result<()> {
+    create_keys_folder()?;
 
-    println!("Keypair '{}' generated successfully!", name);
+    let key = key_type.generate()?;
+    let passphrase = read_new_passphrase(&name)?;
+    write_private_key(&name, &key, &passphrase)?;
+    let public_key = key.public_key_to_pem().map_err(key_error)?;
+    fs::write(key_file(&name, "pk"), public_key).map_err(storage_error)?;
+
+    println!("{} keypair '{}' generated successfully!", key_type, name);
 
     if get_default_keypair().is_none() {
-        set_default_keypair(name);
+        set_default_keypair(name)?;
     }
+    Ok(())
 }
 
-pub fn list_keypairs() {
-    create_keys_folder();
-    let paths = fs::read_dir("./keys")
-        .expect("No keys generated yet")
+fn key_names() -> Result<Vec<String>> {
+    create_keys_folder()?;
+    let names = fs::read_dir(config::get().keys_dir())
+        .map_err(storage_error)?
         .filter_map(|path| {
             path.ok()
                 .map(|x| {
@@ -49,15 +208,37 @@ pub fn list_keypairs() {
                 .flatten()
         })
         .collect::<Vec<_>>();
+    Ok(names)
+}
+
+pub fn list_keypairs() -> Result<()> {
+    let paths = key_names()?;
 
     if paths.len() == 0 {
         println!("No keys generated yet");
     } else {
         println!("Available keys:");
         for path in paths {
-            let key = load_keypair(Some(path.clone()));
-            let address = keypair_to_address(&key);
-            let formatted_address = format_address(&address);
+            let (key, address) = match load_public_key(Some(path.clone()))
+                .and_then(|key| keypair_to_address(&key).map(|address| (key, address)))
+            {
+                Ok(loaded) => loaded,
+                Err(err) => {
+                    println!("- {}  {}", path, err);
+                    continue;
+                }
+            };
+            let lock = if is_encrypted(&path)? {
+                "encrypted"
+            } else {
+                "PLAINTEXT"
+            };
+            let formatted_address = format!(
+                "{}  {}  {}",
+                format_address(&address),
+                KeyType::of(&key)?,
+                lock
+            );
             let is_default = get_default_keypair()
                 .map(|k| k == path)
                 .or(Some(false))
@@ -69,75 +250,669 @@ pub fn list_keypairs() {
             }
         }
     }
+    Ok(())
 }
 
-pub fn delete_key(name: String) {
-    create_keys_folder();
-    if Path::new(&format!("./keys/{}.sk", name)).exists() {
-        fs::remove_file(format!("./keys/{}.sk", name)).unwrap();
-        fs::remove_file(format!("./keys/{}.pk", name)).unwrap();
+/// Re-encrypts a stored key under a new passphrase.
+pub fn change_passphrase(name: String) -> Result<()> {
+    let key = load_keypair(Some(name.clone()))?;
+    let passphrase = read_new_passphrase(&name)?;
+    write_private_key(&name, &key, &passphrase)?;
+    println!("Passphrase of keypair '{}' changed", name);
+    Ok(())
+}
+
+/// Encrypts plaintext keys written by older versions. Migrates every plaintext key if no name
+/// is given.
+pub fn encrypt_keys(name: Option<String>) -> Result<()> {
+    let names = match name {
+        Some(name) => vec![name],
+        None => key_names()?,
+    };
+    let plaintext = names
+        .into_iter()
+        .map(|name| Ok((is_encrypted(&name)?, name)))
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .filter_map(|(encrypted, name)| (!encrypted).then_some(name))
+        .collect::<Vec<_>>();
+    if plaintext.is_empty() {
+        println!("All keys are already encrypted");
+        return Ok(());
+    }
+
+    let passphrase = read_new_passphrase(&plaintext.join(", "))?;
+    for name in plaintext {
+        let key = load_keypair(Some(name.clone()))?;
+        write_private_key(&name, &key, &passphrase)?;
+        println!("Keypair '{}' encrypted", name);
+    }
+    Ok(())
+}
+
+pub fn delete_key(name: String) -> Result<()> {
+    create_keys_folder()?;
+    if key_file(&name, "sk").exists() {
+        fs::remove_file(key_file(&name, "sk")).map_err(storage_error)?;
+        fs::remove_file(key_file(&name, "pk")).map_err(storage_error)?;
         println!("Keypair '{}' deleted", name);
+        Ok(())
     } else {
-        println!("Keypair '{}' does not exist", name);
+        Err(KeyError::KeypairNotFound(name).into())
     }
 }
 
 pub fn get_default_keypair() -> Option<String> {
-    create_keys_folder();
-    let default = fs::read_to_string("./keys/default").ok();
-    return default;
+    create_keys_folder().ok()?;
+    fs::read_to_string(config::get().keys_dir().join("default")).ok()
 }
 
-pub fn set_default_keypair(name: String) {
-    create_keys_folder();
-    if Path::new(&format!("./keys/{}.sk", name)).exists() {
-        fs::write("./keys/default", &name).unwrap();
+pub fn set_default_keypair(name: String) -> Result<()> {
+    create_keys_folder()?;
+    if key_file(&name, "sk").exists() {
+        fs::write(config::get().keys_dir().join("default"), &name).map_err(storage_error)?;
         println!("Default keypair set to '{}'", name);
+        Ok(())
     } else {
-        println!("Keypair '{}' does not exist", name);
+        Err(KeyError::KeypairNotFound(name).into())
+    }
+}
+
+pub fn load_keypair(name: Option<String>) -> Result<PKey<Private>> {
+    create_keys_folder()?;
+    let name = match name {
+        Some(name) => name,
+        None => get_default_keypair().ok_or(KeyError::NoDefaultKeypair)?,
+    };
+    let key = read_private_key(&name)?;
+    KeyType::of(&key)?;
+    Ok(key)
+}
+
+fn read_private_key(name: &str) -> Result<PKey<Private>> {
+    let private_key = fs::read_to_string(key_file(name, "sk"))
+        .map_err(|_| KeyError::KeypairNotFound(name.to_string()))?;
+
+    if !is_encrypted_pem(&private_key) {
+        return PKey::private_key_from_pem(private_key.as_bytes()).map_err(key_error);
     }
+
+    let passphrase = match env::var(PASSPHRASE_ENV) {
+        Ok(passphrase) => passphrase,
+        Err(_) => read_passphrase(&format!("Passphrase for keypair '{}': ", name))?,
+    };
+    let encoded = private_key
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .collect::<String>();
+    let data = base64::decode_block(&encoded)
+        .map_err(|_| Error::Storage(format!("Keypair '{}' is corrupt", name)))?;
+    let der = decrypt_secret(&data, &passphrase)
+        .ok_or_else(|| KeyError::InvalidPassphrase(format!("Can't decrypt keypair '{}'", name)))?;
+    PKey::private_key_from_pkcs8(&der).map_err(key_error)
 }
 
-pub fn load_keypair(name: Option<String>) -> Rsa<Private> {
-    create_keys_folder();
-    let name = name.unwrap_or_else(|| {
-        get_default_keypair().unwrap_or_else(|| panic!("No default keypair set"))
-    });
+/// Loads only the public half of a keypair, which never needs a passphrase.
+pub fn load_public_key(name: Option<String>) -> Result<PKey<Public>> {
+    create_keys_folder()?;
+    let name = match name {
+        Some(name) => name,
+        None => get_default_keypair().ok_or(KeyError::NoDefaultKeypair)?,
+    };
 
-    let private_key = fs::read_to_string(format!("./keys/{}.sk", name)).unwrap();
+    let public_key =
+        fs::read_to_string(key_file(&name, "pk")).map_err(|_| KeyError::KeypairNotFound(name))?;
+    let key = PKey::public_key_from_pem(public_key.as_bytes()).map_err(key_error)?;
+    KeyType::of(&key)?;
+    Ok(key)
+}
+
+fn is_encrypted(name: &str) -> Result<bool> {
+    let private_key = fs::read_to_string(key_file(name, "sk"))
+        .map_err(|_| KeyError::KeypairNotFound(name.to_string()))?;
+    Ok(is_encrypted_pem(&private_key))
+}
 
-    let rsa = Rsa::private_key_from_pem(&private_key.as_bytes()).unwrap();
-    return rsa;
+fn is_encrypted_pem(pem: &str) -> bool {
+    pem.contains(KEYSTORE_PEM_LABEL)
 }
 
-pub fn keypair_to_address<T: HasPublic>(rsa: &Rsa<T>) -> Address {
-    let pk = rsa.public_key_to_der().unwrap();
-    let hash = hash(MessageDigest::sha3_256(), &pk).unwrap();
+/// Writes a private key as its PKCS#8 DER encoding encrypted with [`encrypt_secret`], in PEM
+/// form.
+fn write_private_key(name: &str, key: &PKey<Private>, passphrase: &str) -> Result<()> {
+    let der = key.private_key_to_pkcs8().map_err(key_error)?;
+    let data = encrypt_secret(&der, passphrase)?;
+    let pem = format!(
+        "-----BEGIN {label}-----\n{}\n-----END {label}-----\n",
+        base64::encode_block(&data),
+        label = KEYSTORE_PEM_LABEL
+    );
+    write_secret_file(&key_file(name, "sk"), pem.as_bytes())
+}
+
+/// Writes a file only the current user can read. New files are created with that mode, so their
+/// contents are never readable by others.
+fn write_secret_file(path: &Path, data: &[u8]) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path).map_err(storage_error)?;
+    // An existing file keeps its mode, so tighten it before writing.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))
+            .map_err(storage_error)?;
+    }
+    file.write_all(data).map_err(storage_error)
+}
+
+/// Creates a new wallet seed, prints its phrase and derives the first key from it.
+pub fn generate_seed(prefix: String) -> Result<()> {
+    create_keys_folder()?;
+    if seed_file().exists() {
+        return Err(Error::Storage(format!(
+            "A wallet seed already exists. Remove {} first",
+            seed_file().display()
+        )));
+    }
+
+    let phrase = mnemonic::generate()?;
+    println!("Your seed phrase is:\n\n{}\n", phrase);
+    println!("Write it down and keep it safe. It restores every key derived from this wallet.");
+
+    let passphrase = read_new_passphrase("seed")?;
+    write_seed(&phrase, &passphrase)?;
+    derive_keypairs(&phrase, &prefix, 0..1, &passphrase)
+}
+
+/// Derives the key at `index` from the stored wallet seed.
+pub fn derive_keypair(name: String, index: u32) -> Result<()> {
+    let phrase = load_seed()?;
+    let seed = mnemonic::to_seed(&phrase)?;
+    let key = mnemonic::derive_key(&seed, index)?;
+    let passphrase = read_new_passphrase(&name)?;
+    store_derived_keypair(&name, &key, &passphrase)
+}
+
+/// Stores a seed phrase and regenerates the first `count` keys derived from it.
+pub fn restore_seed(prefix: String, count: u32) -> Result<()> {
+    create_keys_folder()?;
+    if seed_file().exists() {
+        return Err(Error::Storage(format!(
+            "A wallet seed already exists. Remove {} first",
+            seed_file().display()
+        )));
+    }
+
+    let phrase = read_passphrase("Seed phrase: ")?;
+    mnemonic::to_entropy(&phrase)?;
+    let passphrase = read_new_passphrase("seed")?;
+    write_seed(&phrase, &passphrase)?;
+    derive_keypairs(&phrase, &prefix, 0..count, &passphrase)
+}
+
+fn derive_keypairs(
+    phrase: &str,
+    prefix: &str,
+    indices: std::ops::Range<u32>,
+    passphrase: &str,
+) -> Result<()> {
+    let seed = mnemonic::to_seed(phrase)?;
+    for index in indices {
+        let key = mnemonic::derive_key(&seed, index)?;
+        store_derived_keypair(&format!("{}-{}", prefix, index), &key, passphrase)?;
+    }
+    Ok(())
+}
+
+fn store_derived_keypair(name: &str, key: &PKey<Private>, passphrase: &str) -> Result<()> {
+    let address = keypair_to_address(key)?;
+    if key_file(name, "sk").exists() {
+        if keypair_to_address(&load_public_key(Some(name.to_string()))?)? == address {
+            println!("Keypair '{}' already exists", name);
+            return Ok(());
+        }
+        return Err(KeyError::KeypairExists(name.to_string()).into());
+    }
+
+    write_private_key(name, key, passphrase)?;
+    let public_key = key.public_key_to_pem().map_err(key_error)?;
+    fs::write(key_file(name, "pk"), public_key).map_err(storage_error)?;
+    println!("Keypair '{}' derived: {}", name, format_address(&address));
+
+    if get_default_keypair().is_none() {
+        set_default_keypair(name.to_string())?;
+    }
+    Ok(())
+}
+
+/// Stores the seed phrase encrypted with [`encrypt_secret`], after a format byte.
+fn write_seed(phrase: &str, passphrase: &str) -> Result<()> {
+    let mut data = vec![SEED_ENCRYPTED];
+    data.extend_from_slice(&encrypt_secret(phrase.as_bytes(), passphrase)?);
+    write_secret_file(&seed_file(), &data)
+}
+
+fn load_seed() -> Result<String> {
+    let data = fs::read(seed_file())
+        .map_err(|_| Error::Storage("No wallet seed. Run `keys seed` first".to_string()))?;
+    let phrase = match data.split_first() {
+        Some((&SEED_ENCRYPTED, encrypted)) => {
+            let passphrase = match env::var(PASSPHRASE_ENV) {
+                Ok(passphrase) => passphrase,
+                Err(_) => read_passphrase("Passphrase for the wallet seed: ")?,
+            };
+            decrypt_secret(encrypted, &passphrase).ok_or_else(|| {
+                KeyError::InvalidPassphrase("Can't decrypt the wallet seed".to_string())
+            })?
+        }
+        _ => return Err(Error::Storage("Corrupt wallet seed".to_string())),
+    };
+    String::from_utf8(phrase).map_err(|_| Error::Storage("Corrupt wallet seed".to_string()))
+}
+
+/// Encrypts with AES-256-GCM under a key derived from the passphrase with PBKDF2-HMAC-SHA256.
+/// Layout: salt, iv, tag, ciphertext.
+fn encrypt_secret(plaintext: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let mut salt = [0u8; SALT_LEN];
+    let mut iv = [0u8; IV_LEN];
+    let mut tag = [0u8; TAG_LEN];
+    rand_bytes(&mut salt).map_err(key_error)?;
+    rand_bytes(&mut iv).map_err(key_error)?;
+    let key = encryption_key(passphrase, &salt)?;
+    let ciphertext = symm::encrypt_aead(
+        Cipher::aes_256_gcm(),
+        &key,
+        Some(&iv),
+        &[],
+        plaintext,
+        &mut tag,
+    )
+    .map_err(key_error)?;
+    Ok([&salt[..], &iv, &tag, &ciphertext].concat())
+}
+
+/// Reverses [`encrypt_secret`], or returns `None` if the passphrase is wrong or the data was
+/// changed. The tag makes both fail reliably instead of decrypting to garbage.
+fn decrypt_secret(data: &[u8], passphrase: &str) -> Option<Vec<u8>> {
+    if data.len() < SALT_LEN + IV_LEN + TAG_LEN {
+        return None;
+    }
+    let (salt, rest) = data.split_at(SALT_LEN);
+    let (iv, rest) = rest.split_at(IV_LEN);
+    let (tag, ciphertext) = rest.split_at(TAG_LEN);
+    let key = encryption_key(passphrase, salt).ok()?;
+    symm::decrypt_aead(Cipher::aes_256_gcm(), &key, Some(iv), &[], ciphertext, tag).ok()
+}
+
+fn encryption_key(passphrase: &str, salt: &[u8]) -> Result<Vec<u8>> {
+    let mut key = vec![0u8; 32];
+    pbkdf2_hmac(
+        passphrase.as_bytes(),
+        salt,
+        KDF_ITERATIONS,
+        MessageDigest::sha256(),
+        &mut key,
+    )
+    .map_err(key_error)?;
+    Ok(key)
+}
+
+/// Asks for the passphrase that encrypts a new or re-encrypted secret. Empty passphrases are
+/// rejected, so nothing new is ever written unencrypted.
+fn read_new_passphrase(name: &str) -> Result<String> {
+    let passphrase = match env::var(NEW_PASSPHRASE_ENV) {
+        Ok(passphrase) => passphrase,
+        Err(_) => {
+            let passphrase = read_passphrase(&format!("New passphrase for '{}': ", name))?;
+            let confirmation = read_passphrase("Repeat passphrase: ")?;
+            if passphrase != confirmation {
+                return Err(
+                    KeyError::InvalidPassphrase("Passphrases don't match".to_string()).into(),
+                );
+            }
+            passphrase
+        }
+    };
+    if passphrase.is_empty() {
+        return Err(KeyError::InvalidPassphrase("Passphrase can't be empty".to_string()).into());
+    }
+    Ok(passphrase)
+}
+
+fn read_passphrase(prompt: &str) -> Result<String> {
+    eprint!("{}", prompt);
+    io::stderr().flush().map_err(storage_error)?;
+
+    // Hide the passphrase while it's typed. Not every platform has stty, so failures are ignored.
+    let hide = io::stdin().is_terminal();
+    if hide {
+        let _ = Command::new("stty")
+            .arg("-echo")
+            .stdin(Stdio::inherit())
+            .status();
+    }
+    let mut passphrase = String::new();
+    let read = io::stdin().read_line(&mut passphrase);
+    if hide {
+        let _ = Command::new("stty")
+            .arg("echo")
+            .stdin(Stdio::inherit())
+            .status();
+        eprintln!();
+    }
+    read.map_err(storage_error)?;
+    Ok(passphrase.trim_end_matches(['\r', '\n']).to_string())
+}
+
+pub fn keypair_to_address<T: HasPublic>(key: &PKey<T>) -> Result<Address> {
+    public_key_to_address(&encode_public_key(key)?)
+}
+
+/// Derives the address controlled by an encoded public key, see [`encode_public_key`].
+pub fn public_key_to_address(public_key: &PublicKey) -> Result<Address> {
+    let (key_type, key) = decode_public_key(public_key)?;
+    let hash = match key_type.address_version() {
+        ADDRESS_VERSION_LEGACY => {
+            let der = key.public_key_to_der().map_err(key_error)?;
+            hash(MessageDigest::sha3_256(), &der)
+        }
+        version => {
+            let mut data = vec![version];
+            data.extend_from_slice(public_key);
+            hash(MessageDigest::sha3_256(), &data)
+        }
+    }
+    .map_err(key_error)?;
     let mut address: Address = [0u8; 16];
     address.copy_from_slice(&hash[0..16]);
-    return address;
+    Ok(address)
+}
+
+/// Encodes a public key as its key type tag followed by the key in the most compact form for
+/// that type: DER for RSA, raw bytes for Ed25519 and a compressed point for secp256k1.
+pub fn encode_public_key<T: HasPublic>(key: &PKey<T>) -> Result<PublicKey> {
+    let key_type = KeyType::of(key)?;
+    let bytes = match key_type {
+        KeyType::Rsa2048 => key.public_key_to_der(),
+        KeyType::Ed25519 => key.raw_public_key(),
+        KeyType::Secp256k1 => key.ec_key().and_then(|ec| {
+            let mut ctx = BigNumContext::new()?;
+            ec.public_key()
+                .to_bytes(ec.group(), PointConversionForm::COMPRESSED, &mut ctx)
+        }),
+    }
+    .map_err(key_error)?;
+    let mut encoded = vec![key_type.tag()];
+    encoded.extend_from_slice(&bytes);
+    Ok(encoded)
+}
+
+pub fn decode_public_key(public_key: &PublicKey) -> Result<(KeyType, PKey<Public>)> {
+    let (tag, bytes) = public_key
+        .split_first()
+        .ok_or_else(|| KeyError::InvalidKey("Empty public key".to_string()))?;
+    let key_type = KeyType::from_tag(*tag)?;
+    // OpenSSL also parses uncompressed points, which would give the same key a second address.
+    if key_type == KeyType::Secp256k1 && bytes.len() != COMPRESSED_POINT_LEN {
+        return Err(KeyError::InvalidKey("secp256k1 keys must be compressed".to_string()).into());
+    }
+    let key = match key_type {
+        KeyType::Rsa2048 => Rsa::public_key_from_der(bytes).and_then(PKey::from_rsa),
+        KeyType::Ed25519 => PKey::public_key_from_raw_bytes(bytes, Id::ED25519),
+        KeyType::Secp256k1 => EcGroup::from_curve_name(Nid::SECP256K1).and_then(|group| {
+            let mut ctx = BigNumContext::new()?;
+            let point = EcPoint::from_bytes(&group, bytes, &mut ctx)?;
+            PKey::from_ec_key(EcKey::from_public_key(&group, &point)?)
+        }),
+    }
+    .map_err(key_error)?;
+    if KeyType::of(&key)? != key_type {
+        return Err(KeyError::InvalidKey("Key type mismatch".to_string()).into());
+    }
+    Ok((key_type, key))
+}
+
+pub fn sign(key: &PKey<Private>, data: &[u8]) -> Result<Vec<u8>> {
+    let signature = match KeyType::of(key)? {
+        // Ed25519 hashes internally and only supports one-shot signing.
+        KeyType::Ed25519 => {
+            Signer::new_without_digest(key).and_then(|mut signer| signer.sign_oneshot_to_vec(data))
+        }
+        KeyType::Rsa2048 | KeyType::Secp256k1 => Signer::new(MessageDigest::sha3_256(), key)
+            .and_then(|mut signer| {
+                signer.update(data)?;
+                signer.sign_to_vec()
+            }),
+    };
+    signature.map_err(key_error)
+}
+
+/// Checks `signature` over `data` for an encoded public key. Malformed keys are never valid.
+pub fn verify(public_key: &PublicKey, data: &[u8], signature: &[u8]) -> bool {
+    let (key_type, key) = match decode_public_key(public_key) {
+        Ok(key) => key,
+        Err(_) => return false,
+    };
+    let valid = match key_type {
+        KeyType::Ed25519 => Verifier::new_without_digest(&key)
+            .and_then(|mut verifier| verifier.verify_oneshot(signature, data)),
+        KeyType::Rsa2048 | KeyType::Secp256k1 => Verifier::new(MessageDigest::sha3_256(), &key)
+            .and_then(|mut verifier| {
+                verifier.update(data)?;
+                verifier.verify(signature)
+            }),
+    };
+    valid.unwrap_or(false)
 }
 
 pub fn format_address(address: &Address) -> String {
+    bech32::encode(config::get().network.address_prefix(), address)
+}
+
+pub fn format_legacy_address(address: &Address) -> String {
     let mut address_str = String::from("0x");
     for byte in address.iter() {
         address_str.push_str(&format!("{:02x}", byte));
     }
-    return address_str;
+    address_str
+}
+
+/// Parses a checksummed `zen1...` address. Legacy `0x` hex addresses are still accepted.
+pub fn parse_address(string: &str) -> Result<Address> {
+    let string = string.trim();
+    if string.starts_with("0x") {
+        return parse_legacy_address(string);
+    }
+
+    let (prefix, data) = bech32::decode(string)
+        .map_err(|msg| KeyError::InvalidAddress(format!("'{}': {}", string, msg)))?;
+    let expected = config::get().network.address_prefix();
+    if prefix != expected {
+        return Err(KeyError::InvalidAddress(format!(
+            "'{}' is for network '{}', expected '{}'",
+            string, prefix, expected
+        ))
+        .into());
+    }
+    let address: Address = data
+        .try_into()
+        .map_err(|_| KeyError::InvalidAddress(format!("'{}' has the wrong length", string)))?;
+    Ok(address)
 }
 
-pub fn parse_address(string: &str) -> Address {
+fn parse_legacy_address(string: &str) -> Result<Address> {
     let mut address: Address = [0u8; 16];
     if string.len() != 34 {
-        panic!("Invalid address length");
+        return Err(KeyError::InvalidAddress(format!(
+            "'{}' should be 0x followed by 32 hex characters",
+            string
+        ))
+        .into());
     }
     let start_offset = 2;
     for (i, byte) in address.iter_mut().enumerate() {
-        *byte = u8::from_str_radix(
-            &string[(i * 2 + start_offset)..(i * 2 + 2 + start_offset)],
-            16,
-        )
-        .unwrap();
+        *byte = string
+            .get((i * 2 + start_offset)..(i * 2 + 2 + start_offset))
+            .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| KeyError::InvalidAddress(format!("'{}' is not valid hex", string)))?;
+    }
+    Ok(address)
+}
+
+/// Formats a block hash or transaction id as hex.
+pub fn format_hash(hash: &Hash) -> String {
+    script::to_hex(hash)
+}
+
+/// Parses a block hash or transaction id, with or without a `0x` prefix.
+pub fn parse_hash(string: &str) -> Result<Hash> {
+    let string = string.trim();
+    script::parse_hex(string.strip_prefix("0x").unwrap_or(string))
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| {
+            ValidationError::InvalidHash(format!("'{}' should be 64 hex characters", string)).into()
+        })
+}
+
+/// Formats an amount of base units as a decimal ZEN value, e.g. `1250000000` => `12.5`.
+pub fn format_amount(amount: u128) -> String {
+    let whole = amount / COIN;
+    let fraction = amount % COIN;
+    if fraction == 0 {
+        return whole.to_string();
+    }
+    let fraction = format!("{:0width$}", fraction, width = COIN_DECIMALS as usize);
+    format!("{}.{}", whole, fraction.trim_end_matches('0'))
+}
+
+/// Parses a decimal ZEN value such as `12.5` into base units.
+pub fn parse_amount(string: &str) -> Result<u128> {
+    let string = string.trim();
+    let (whole, fraction) = match string.split_once('.') {
+        Some((whole, fraction)) => (whole, fraction),
+        None => (string, ""),
+    };
+    let is_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    if (whole.is_empty() && fraction.is_empty()) || !is_digits(whole) || !is_digits(fraction) {
+        return Err(invalid_amount(format!("'{}' is not a number", string)));
+    }
+    if fraction.len() > COIN_DECIMALS as usize {
+        return Err(invalid_amount(format!(
+            "'{}' has more than {} decimal places",
+            string, COIN_DECIMALS
+        )));
+    }
+
+    let whole: u128 = if whole.is_empty() {
+        0
+    } else {
+        whole
+            .parse()
+            .map_err(|_| invalid_amount(format!("'{}' is too large", string)))?
+    };
+    let fraction: u128 = format!("{:0<width$}", fraction, width = COIN_DECIMALS as usize)
+        .parse()
+        .map_err(|_| invalid_amount(format!("'{}' is too large", string)))?;
+
+    whole
+        .checked_mul(COIN)
+        .and_then(|whole| whole.checked_add(fraction))
+        .ok_or_else(|| invalid_amount(format!("'{}' is too large", string)))
+}
+
+fn invalid_amount(msg: String) -> Error {
+    Error::Validation(ValidationError::InvalidAmount(msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secp256k1_keys_must_be_compressed() {
+        let key = KeyType::Secp256k1.generate().unwrap();
+        let compressed = encode_public_key(&key).unwrap();
+        assert_eq!(compressed.len(), 1 + COMPRESSED_POINT_LEN);
+        assert!(decode_public_key(&compressed).is_ok());
+
+        let ec = key.ec_key().unwrap();
+        let mut ctx = BigNumContext::new().unwrap();
+        let mut uncompressed = vec![KeyType::Secp256k1.tag()];
+        uncompressed.extend(
+            ec.public_key()
+                .to_bytes(ec.group(), PointConversionForm::UNCOMPRESSED, &mut ctx)
+                .unwrap(),
+        );
+        assert!(decode_public_key(&uncompressed).is_err());
+        assert!(!verify(
+            &uncompressed,
+            b"data",
+            &sign(&key, b"data").unwrap()
+        ));
+    }
+
+    #[test]
+    fn parse_amount_accepts_decimals() {
+        assert_eq!(parse_amount("12").unwrap(), 12 * COIN);
+        assert_eq!(parse_amount(" 12.5 ").unwrap(), 12 * COIN + COIN / 2);
+        assert_eq!(parse_amount("0.00000001").unwrap(), 1);
+        assert_eq!(parse_amount(".5").unwrap(), COIN / 2);
+        assert_eq!(parse_amount("5.").unwrap(), 5 * COIN);
+    }
+
+    #[test]
+    fn parse_amount_rejects_malformed_amounts() {
+        for amount in ["", " ", ".", "1.2.3", "-1", "1e3", "1,5", "0.000000001"] {
+            assert!(parse_amount(amount).is_err(), "{:?}", amount);
+        }
+    }
+
+    #[test]
+    fn parse_amount_rejects_overflow() {
+        let max_whole = u128::MAX / COIN;
+        assert_eq!(
+            parse_amount(&max_whole.to_string()).unwrap(),
+            max_whole * COIN
+        );
+        assert!(parse_amount(&(max_whole + 1).to_string()).is_err());
+        assert!(parse_amount(&format!("{}0", u128::MAX)).is_err());
+        assert!(parse_amount(&format!("{}.99999999", max_whole)).is_err());
+    }
+
+    #[test]
+    fn format_amount_round_trip() {
+        assert_eq!(format_amount(0), "0");
+        assert_eq!(format_amount(12 * COIN), "12");
+        assert_eq!(format_amount(12 * COIN + COIN / 2), "12.5");
+        assert_eq!(format_amount(1), "0.00000001");
+        for amount in [0, 1, COIN - 1, COIN, 12 * COIN + COIN / 2, u128::MAX] {
+            assert_eq!(parse_amount(&format_amount(amount)).unwrap(), amount);
+        }
+    }
+
+    #[test]
+    fn encrypted_secret_round_trip() {
+        let data = encrypt_secret(b"secret", "passphrase").unwrap();
+        assert_eq!(
+            decrypt_secret(&data, "passphrase").as_deref(),
+            Some(&b"secret"[..])
+        );
+    }
+
+    #[test]
+    fn encrypted_secret_rejects_wrong_passphrase_and_tampering() {
+        let mut data = encrypt_secret(b"secret", "passphrase").unwrap();
+        assert_eq!(decrypt_secret(&data, "wrong"), None);
+
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert_eq!(decrypt_secret(&data, "passphrase"), None);
+        assert_eq!(decrypt_secret(&data[..20], "passphrase"), None);
     }
-    return address;
 }
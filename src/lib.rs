@@ -1,7 +1,15 @@
+pub mod bech32;
 pub mod block;
 pub mod blockchain;
 pub mod client;
+pub mod config;
+pub mod error;
 pub mod keys;
+pub mod mempool;
+pub mod merkle;
+pub mod mnemonic;
+pub mod multisig;
+pub mod script;
 pub mod server;
 pub mod transaction;
 pub mod types;
@@ -0,0 +1,513 @@
+use std::time::{Duration, Instant};
+
+use crate::{
+    blockchain::World,
+    error::{Result, ValidationError},
+    keys,
+    transaction::Transaction,
+    types::{Address, Hash, COIN},
+};
+
+/// Most transactions the mempool holds ready for mining. When it's full, a new transaction
+/// evicts the one with the lowest fee if it pays more.
+pub const MAX_PENDING: usize = 10_000;
+/// Most transactions the mempool holds queued across all senders.
+pub const MAX_QUEUED: usize = 1_000;
+/// Smallest fee increase of a replacement, so transactions of every fee level cost something to
+/// relay again.
+pub const MIN_RELAY_FEE: u128 = COIN / 1000;
+/// Smallest fee increase of a replacement relative to the fee it replaces.
+pub const MIN_FEE_BUMP_PERCENT: u128 = 10;
+
+/// Most transactions a sender can have queued ahead of its next index.
+pub const MAX_QUEUED_PER_SENDER: usize = 16;
+/// How long a queued transaction waits for the gap before it to be filled.
+pub const QUEUE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Transactions accepted by the node but not mined yet, in the order they arrived. Each one is
+/// valid on top of the chain tip plus every transaction before it.
+///
+/// Transactions whose index is ahead of the sender's next one wait in a queue and move over once
+/// the transactions before them arrive.
+#[derive(Debug)]
+pub struct Mempool {
+    transactions: Vec<Transaction>,
+    queued: Vec<Queued>,
+    max_pending: usize,
+    max_queued: usize,
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Mempool::new()
+    }
+}
+
+#[derive(Debug)]
+struct Queued {
+    transaction: Transaction,
+    received: Instant,
+}
+
+impl Mempool {
+    pub fn new() -> Mempool {
+        Mempool {
+            transactions: Vec::new(),
+            queued: Vec::new(),
+            max_pending: MAX_PENDING,
+            max_queued: MAX_QUEUED,
+        }
+    }
+
+    pub fn transactions(&self) -> &[Transaction] {
+        &self.transactions
+    }
+
+    /// Whether the transaction with this id is pending or queued.
+    pub fn contains(&self, id: &Hash) -> bool {
+        self.transactions
+            .iter()
+            .chain(self.queued.iter().map(|q| &q.transaction))
+            .any(|t| t.id() == *id)
+    }
+
+    /// Pending and queued transactions of `sender`, ordered by index.
+    pub fn pending(&self, sender: &Address) -> Vec<Transaction> {
+        let mut pending = self
+            .transactions
+            .iter()
+            .chain(self.queued.iter().map(|q| &q.transaction))
+            .filter(|t| t.sender == *sender)
+            .cloned()
+            .collect::<Vec<_>>();
+        pending.sort_by_key(|t| t.index);
+        pending
+    }
+
+    /// Adds a transaction on top of `world`, the state at the chain tip. A transaction with the
+    /// same sender and index as a pending one replaces it if it pays at least
+    /// [`min_replacement_fee`].
+    pub fn insert(&mut self, transaction: Transaction, world: &World, height: u128) -> Result<()> {
+        self.drop_stale_queued(height);
+        let existing = self
+            .transactions
+            .iter()
+            .position(|t| t.sender == transaction.sender && t.index == transaction.index);
+        let position = match existing {
+            Some(position) => {
+                check_replacement(&self.transactions[position], &transaction)?;
+                position
+            }
+            None => {
+                let next = self
+                    .tip(world)
+                    .get_account_state(&transaction.sender)
+                    .transaction_index
+                    + 1;
+                if transaction.index > next {
+                    return self.enqueue(transaction, next);
+                }
+                self.transactions.len()
+            }
+        };
+
+        let mut state = world.clone();
+        for pending in &self.transactions[..position] {
+            state.update_on_transaction(pending);
+        }
+        transaction.is_valid(&state)?;
+        state.update_on_transaction(&transaction);
+
+        match existing {
+            Some(position) => {
+                println!(
+                    "Replacing pending transaction {} of {}",
+                    transaction.index,
+                    keys::format_address(&transaction.sender)
+                );
+                self.transactions[position] = transaction;
+                // A higher fee can leave later transactions of the sender unfunded.
+                let tail = self.transactions.split_off(position + 1);
+                self.transactions
+                    .extend(Mempool::still_valid(tail, &state, height));
+            }
+            None => {
+                if self.transactions.len() >= self.max_pending {
+                    self.evict(&transaction, world, height)?;
+                }
+                self.transactions.push(transaction);
+            }
+        }
+        self.promote(world);
+        Ok(())
+    }
+
+    /// Re-checks every pending transaction against a new chain tip, dropping the ones that were
+    /// mined, expired or became invalid.
+    pub fn update(&mut self, world: &World, height: u128) {
+        let transactions = std::mem::take(&mut self.transactions);
+        self.transactions = Mempool::still_valid(transactions, world, height);
+        self.drop_stale_queued(height);
+        self.promote(world);
+    }
+
+    fn enqueue(&mut self, transaction: Transaction, next: u128) -> Result<()> {
+        transaction.is_well_formed()?;
+        let sender = transaction.sender;
+        if transaction.index - next >= MAX_QUEUED_PER_SENDER as u128 {
+            return Err(ValidationError::InvalidTransactionIndex {
+                expected: next,
+                actual: transaction.index,
+            }
+            .into());
+        }
+        let queued = Queued {
+            transaction,
+            received: Instant::now(),
+        };
+        let existing = self.queued.iter().position(|q| {
+            q.transaction.sender == sender && q.transaction.index == queued.transaction.index
+        });
+        match existing {
+            Some(position) => {
+                check_replacement(&self.queued[position].transaction, &queued.transaction)?;
+                self.queued[position] = queued;
+            }
+            None => {
+                if self.queued.len() >= self.max_queued {
+                    let (position, lowest) = lowest_fee(self.queued.iter().map(|q| &q.transaction));
+                    if queued.transaction.fee <= lowest {
+                        return Err(ValidationError::MempoolFull { lowest_fee: lowest }.into());
+                    }
+                    let evicted = self.queued.remove(position);
+                    println!(
+                        "Mempool full, dropping queued transaction: {}",
+                        evicted.transaction
+                    );
+                }
+                self.queued.push(queued);
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes room for `transaction` by dropping the pending transaction with the lowest fee,
+    /// along with the ones that can't be mined without it. Nothing is dropped unless
+    /// `transaction` pays more and is still valid afterwards, since it may have depended on the
+    /// evicted one.
+    fn evict(&mut self, transaction: &Transaction, world: &World, height: u128) -> Result<()> {
+        let (position, lowest) = lowest_fee(self.transactions.iter());
+        if transaction.fee <= lowest {
+            return Err(ValidationError::MempoolFull { lowest_fee: lowest }.into());
+        }
+        let mut remaining = self.transactions.clone();
+        let evicted = remaining.remove(position);
+        let remaining = Mempool::still_valid(remaining, world, height);
+        let mut tip = world.clone();
+        for pending in &remaining {
+            tip.update_on_transaction(pending);
+        }
+        transaction.is_valid(&tip)?;
+
+        println!("Mempool full, dropping transaction: {}", evicted);
+        self.transactions = remaining;
+        Ok(())
+    }
+
+    /// Moves queued transactions whose turn has come over to the pending ones.
+    fn promote(&mut self, world: &World) {
+        let mut tip = self.tip(world);
+        let next = |tip: &World, transaction: &Transaction| {
+            tip.get_account_state(&transaction.sender).transaction_index + 1
+        };
+        while let Some(position) = self
+            .queued
+            .iter()
+            .position(|q| q.transaction.index == next(&tip, &q.transaction))
+        {
+            let transaction = self.queued.remove(position).transaction;
+            match transaction.is_valid(&tip) {
+                Ok(()) => {
+                    tip.update_on_transaction(&transaction);
+                    self.transactions.push(transaction);
+                }
+                Err(err) => println!("Dropping queued transaction {}: {}", transaction, err),
+            }
+        }
+        // Indices that were taken in the meantime can never be mined.
+        self.queued
+            .retain(|q| q.transaction.index > next(&tip, &q.transaction));
+    }
+
+    fn drop_stale_queued(&mut self, height: u128) {
+        self.queued.retain(|q| {
+            let stale = q.received.elapsed() > QUEUE_TIMEOUT || q.transaction.is_expired(height);
+            if stale {
+                println!("Dropping queued transaction: {}", q.transaction);
+            }
+            !stale
+        });
+    }
+
+    /// The state after every pending transaction.
+    fn tip(&self, world: &World) -> World {
+        let mut tip = world.clone();
+        for transaction in &self.transactions {
+            tip.update_on_transaction(transaction);
+        }
+        tip
+    }
+
+    /// Keeps the transactions that are valid in order on top of `world`, which must already
+    /// include every transaction before them.
+    fn still_valid(
+        transactions: Vec<Transaction>,
+        world: &World,
+        height: u128,
+    ) -> Vec<Transaction> {
+        let mut world = world.clone();
+        let mut valid = Vec::new();
+        for transaction in transactions {
+            if transaction.is_expired(height) {
+                println!("Dropping expired transaction: {}", transaction);
+                continue;
+            }
+            if transaction.is_valid(&world).is_err() {
+                continue;
+            }
+            world.update_on_transaction(&transaction);
+            valid.push(transaction);
+        }
+        valid
+    }
+}
+
+/// The lowest fee a transaction replacing one that pays `pending_fee` must pay: at least
+/// [`MIN_FEE_BUMP_PERCENT`] and [`MIN_RELAY_FEE`] more.
+pub fn min_replacement_fee(pending_fee: u128) -> u128 {
+    let bump = (pending_fee * MIN_FEE_BUMP_PERCENT).div_ceil(100);
+    pending_fee.saturating_add(bump.max(MIN_RELAY_FEE))
+}
+
+fn check_replacement(pending: &Transaction, replacement: &Transaction) -> Result<()> {
+    let minimum = min_replacement_fee(pending.fee);
+    if replacement.fee < minimum {
+        return Err(ValidationError::ReplacementFeeTooLow {
+            pending_fee: pending.fee,
+            fee: replacement.fee,
+            minimum,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Position and fee of the transaction with the lowest fee, the latest one among equal fees.
+/// There must be at least one transaction.
+fn lowest_fee<'a>(
+    transactions: impl DoubleEndedIterator<Item = &'a Transaction> + ExactSizeIterator,
+) -> (usize, u128) {
+    transactions
+        .enumerate()
+        .rev()
+        .min_by_key(|(_, transaction)| transaction.fee)
+        .map(|(position, transaction)| (position, transaction.fee))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::pkey::{PKey, Private};
+
+    use super::*;
+    use crate::{block::Block, config, error::Error};
+
+    /// Keys whose accounts each mined one block, along with the states after those blocks.
+    fn funded_accounts(count: usize) -> (Vec<PKey<Private>>, World) {
+        config::init_for_tests();
+        let keys = (0..count)
+            .map(|_| PKey::generate_ed25519().unwrap())
+            .collect::<Vec<_>>();
+        let mut world = World::new();
+        let mut parent: Option<Block> = None;
+        for key in &keys {
+            let miner = keys::keypair_to_address(key).unwrap();
+            let block = Block::new(parent.as_ref(), &world, &[], &miner);
+            world.update_on_block(&block);
+            parent = Some(block);
+        }
+        (keys, world)
+    }
+
+    fn payment(key: &PKey<Private>, amount: u128, fee: u128, index: u128) -> Transaction {
+        let sender = keys::keypair_to_address(key).unwrap();
+        let mut transaction = Transaction::new(sender, [9u8; 16], amount, index);
+        transaction.fee = fee;
+        transaction.sign(key).unwrap();
+        transaction
+    }
+
+    fn is_fee_too_low(result: Result<()>) -> bool {
+        matches!(
+            result,
+            Err(Error::Validation(
+                ValidationError::ReplacementFeeTooLow { .. }
+            ))
+        )
+    }
+
+    #[test]
+    fn replacement_needs_a_minimum_fee_bump() {
+        let (keys, world) = funded_accounts(1);
+        let mut mempool = Mempool::new();
+        mempool
+            .insert(payment(&keys[0], COIN, 10 * COIN, 1), &world, 1)
+            .unwrap();
+
+        let small_bump = payment(&keys[0], COIN, 10 * COIN + COIN / 2, 1);
+        assert!(is_fee_too_low(mempool.insert(small_bump, &world, 1)));
+
+        let replacement = payment(&keys[0], COIN, 11 * COIN, 1);
+        mempool.insert(replacement.clone(), &world, 1).unwrap();
+        assert_eq!(mempool.transactions(), [replacement]);
+    }
+
+    #[test]
+    fn replacing_a_free_transaction_costs_the_relay_fee() {
+        assert_eq!(min_replacement_fee(0), MIN_RELAY_FEE);
+        assert_eq!(min_replacement_fee(100 * COIN), 110 * COIN);
+
+        let (keys, world) = funded_accounts(1);
+        let mut mempool = Mempool::new();
+        mempool
+            .insert(payment(&keys[0], COIN, 0, 1), &world, 1)
+            .unwrap();
+        let too_low = payment(&keys[0], COIN, MIN_RELAY_FEE - 1, 1);
+        assert!(is_fee_too_low(mempool.insert(too_low, &world, 1)));
+        let replacement = payment(&keys[0], COIN, MIN_RELAY_FEE, 1);
+        mempool.insert(replacement.clone(), &world, 1).unwrap();
+        assert_eq!(mempool.transactions(), [replacement]);
+    }
+
+    #[test]
+    fn replacement_drops_transactions_it_leaves_unfunded() {
+        let (keys, world) = funded_accounts(1);
+        let balance = world
+            .get_account_state(&keys::keypair_to_address(&keys[0]).unwrap())
+            .balance;
+        let mut mempool = Mempool::new();
+        mempool
+            .insert(payment(&keys[0], COIN, COIN, 1), &world, 1)
+            .unwrap();
+        mempool
+            .insert(payment(&keys[0], balance - 2 * COIN, 0, 2), &world, 1)
+            .unwrap();
+        assert_eq!(mempool.transactions().len(), 2);
+
+        let replacement = payment(&keys[0], COIN, 2 * COIN, 1);
+        mempool.insert(replacement.clone(), &world, 1).unwrap();
+        assert_eq!(mempool.transactions(), [replacement]);
+    }
+
+    #[test]
+    fn full_mempool_evicts_the_lowest_fee() {
+        let (keys, world) = funded_accounts(4);
+        let mut mempool = Mempool {
+            max_pending: 2,
+            ..Mempool::new()
+        };
+        let low = payment(&keys[0], COIN, COIN, 1);
+        let middle = payment(&keys[1], COIN, 2 * COIN, 1);
+        let high = payment(&keys[2], COIN, 3 * COIN, 1);
+        mempool.insert(low, &world, 4).unwrap();
+        mempool.insert(middle.clone(), &world, 4).unwrap();
+        mempool.insert(high.clone(), &world, 4).unwrap();
+        assert_eq!(mempool.transactions(), [middle.clone(), high.clone()]);
+
+        let result = mempool.insert(payment(&keys[3], COIN, 2 * COIN, 1), &world, 4);
+        assert_eq!(
+            result,
+            Err(ValidationError::MempoolFull {
+                lowest_fee: 2 * COIN
+            }
+            .into())
+        );
+        assert_eq!(mempool.transactions(), [middle, high]);
+    }
+
+    #[test]
+    fn queued_transactions_are_promoted_once_the_gap_is_filled() {
+        let (keys, world) = funded_accounts(1);
+        let sender = keys::keypair_to_address(&keys[0]).unwrap();
+        let mut mempool = Mempool::new();
+        let transactions = (1..=3)
+            .map(|index| payment(&keys[0], COIN, 0, index))
+            .collect::<Vec<_>>();
+
+        mempool.insert(transactions[2].clone(), &world, 1).unwrap();
+        mempool.insert(transactions[1].clone(), &world, 1).unwrap();
+        assert!(mempool.transactions().is_empty());
+        assert_eq!(mempool.pending(&sender), transactions[1..]);
+
+        mempool.insert(transactions[0].clone(), &world, 1).unwrap();
+        assert_eq!(mempool.transactions(), transactions);
+    }
+
+    #[test]
+    fn queued_transactions_are_promoted_when_the_gap_is_mined() {
+        let (keys, mut world) = funded_accounts(1);
+        let mut mempool = Mempool::new();
+        let first = payment(&keys[0], COIN, 0, 1);
+        let second = payment(&keys[0], COIN, 0, 2);
+        mempool.insert(second.clone(), &world, 1).unwrap();
+        assert!(mempool.transactions().is_empty());
+
+        world.update_on_transaction(&first);
+        mempool.update(&world, 2);
+        assert_eq!(mempool.transactions(), [second]);
+    }
+
+    #[test]
+    fn queue_is_limited_per_sender() {
+        let (keys, world) = funded_accounts(1);
+        let mut mempool = Mempool::new();
+        let last = MAX_QUEUED_PER_SENDER as u128;
+        mempool
+            .insert(payment(&keys[0], COIN, 0, last), &world, 1)
+            .unwrap();
+        let result = mempool.insert(payment(&keys[0], COIN, 0, last + 1), &world, 1);
+        assert_eq!(
+            result,
+            Err(ValidationError::InvalidTransactionIndex {
+                expected: 1,
+                actual: last + 1
+            }
+            .into())
+        );
+    }
+
+    #[test]
+    fn rejected_transaction_evicts_nothing() {
+        let (keys, world) = funded_accounts(2);
+        let mut mempool = Mempool {
+            max_pending: 2,
+            ..Mempool::new()
+        };
+        let low = payment(&keys[0], COIN, COIN, 1);
+        let other = payment(&keys[1], COIN, 2 * COIN, 1);
+        mempool.insert(low.clone(), &world, 2).unwrap();
+        mempool.insert(other.clone(), &world, 2).unwrap();
+
+        // Pays enough to evict `low`, but can't be mined without it.
+        let dependent = payment(&keys[0], COIN, 5 * COIN, 2);
+        assert_eq!(
+            mempool.insert(dependent, &world, 2),
+            Err(ValidationError::InvalidTransactionIndex {
+                expected: 1,
+                actual: 2
+            }
+            .into())
+        );
+        assert_eq!(mempool.transactions(), [low, other]);
+    }
+}
@@ -0,0 +1,376 @@
+use openssl::hash::{hash, MessageDigest};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    block::BlockHeader,
+    blockchain::AccountState,
+    transaction::Transaction,
+    types::{Address, Hash},
+};
+
+// Leaves and inner nodes are hashed with different prefixes so an inner node can't be passed
+// off as a leaf.
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+/// The state tree has one leaf for every possible address, one level per address bit.
+pub const STATE_TREE_DEPTH: usize = 8 * std::mem::size_of::<Address>();
+/// Hash of a subtree without accounts, at any depth.
+const EMPTY: Hash = [0u8; 32];
+
+/// An address and the leaf of its account, see [`account_leaf`].
+type Leaf = (Address, Hash);
+
+/// Proves that a leaf is part of a Merkle tree: the sibling hashes on the path from the leaf to
+/// the root, lowest first.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub steps: Vec<ProofStep>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProofStep {
+    pub sibling: Hash,
+    /// Whether the sibling is the left child.
+    pub left: bool,
+}
+
+/// A transaction together with the proof that it is included in the block with `block_hash`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InclusionProof {
+    pub transaction: Transaction,
+    pub height: u128,
+    pub block_hash: Hash,
+    pub proof: MerkleProof,
+}
+
+/// The root of the tree over `hashes`. A node without a sibling moves up a level unchanged, and
+/// the root of an empty tree is all zeros.
+pub fn root(hashes: &[Hash]) -> Hash {
+    let mut level = hashes.iter().map(leaf_hash).collect::<Vec<_>>();
+    if level.is_empty() {
+        return [0u8; 32];
+    }
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => node_hash(left, right),
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level[0]
+}
+
+/// Builds the proof for the leaf at `position`, or `None` if there is no such leaf.
+pub fn prove(hashes: &[Hash], position: usize) -> Option<MerkleProof> {
+    if position >= hashes.len() {
+        return None;
+    }
+    let mut level = hashes.iter().map(leaf_hash).collect::<Vec<_>>();
+    let mut position = position;
+    let mut steps = Vec::new();
+    while level.len() > 1 {
+        let sibling = position ^ 1;
+        if sibling < level.len() {
+            steps.push(ProofStep {
+                sibling: level[sibling],
+                left: sibling < position,
+            });
+        }
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => node_hash(left, right),
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+        position /= 2;
+    }
+    Some(MerkleProof { steps })
+}
+
+/// Proves the state of one account against a state root: the sibling hashes on the path from the
+/// account's leaf to the root, lowest first. `None` stands for a subtree without accounts.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateProof {
+    pub siblings: Vec<Option<Hash>>,
+}
+
+/// An account state together with the proof that it is the state after the block with
+/// `block_hash`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AccountProof {
+    pub state: AccountState,
+    pub height: u128,
+    pub block_hash: Hash,
+    pub proof: StateProof,
+}
+
+/// The root of the sparse Merkle tree over `leaves`, which are `(address, account_leaf)` pairs
+/// sorted by address.
+pub fn state_root(leaves: &[Leaf]) -> Hash {
+    subtree_root(leaves, 0)
+}
+
+/// Builds the proof for the leaf of `address`, which may be empty.
+pub fn prove_state(leaves: &[Leaf], address: &Address) -> StateProof {
+    let mut siblings = Vec::with_capacity(STATE_TREE_DEPTH);
+    let mut leaves = leaves;
+    for depth in 0..STATE_TREE_DEPTH {
+        let (left, right) = split(leaves, depth);
+        let (own, other) = if bit(address, depth) {
+            (right, left)
+        } else {
+            (left, right)
+        };
+        let sibling = subtree_root(other, depth + 1);
+        siblings.push((sibling != EMPTY).then_some(sibling));
+        leaves = own;
+    }
+    siblings.reverse();
+    StateProof { siblings }
+}
+
+/// The leaf of an account. Accounts that were never used have the empty leaf, so they need no
+/// entry in the tree.
+pub fn account_leaf(state: &AccountState) -> Hash {
+    if *state == AccountState::new(state.address) {
+        return EMPTY;
+    }
+    digest(&[&[LEAF_PREFIX], &bincode::serialize(state).unwrap()])
+}
+
+fn subtree_root(leaves: &[Leaf], depth: usize) -> Hash {
+    match leaves {
+        [] => EMPTY,
+        [(_, leaf), ..] if depth == STATE_TREE_DEPTH => *leaf,
+        _ => {
+            let (left, right) = split(leaves, depth);
+            sparse_node_hash(
+                &subtree_root(left, depth + 1),
+                &subtree_root(right, depth + 1),
+            )
+        }
+    }
+}
+
+/// Splits sorted leaves into the ones whose address has bit `depth` unset and the ones where
+/// it's set.
+fn split(leaves: &[Leaf], depth: usize) -> (&[Leaf], &[Leaf]) {
+    leaves.split_at(leaves.partition_point(|(address, _)| !bit(address, depth)))
+}
+
+/// Bit `depth` of the address, most significant first, so sorted addresses stay sorted within
+/// each subtree.
+fn bit(address: &Address, depth: usize) -> bool {
+    address[depth / 8] >> (7 - depth % 8) & 1 == 1
+}
+
+fn sparse_node_hash(left: &Hash, right: &Hash) -> Hash {
+    if *left == EMPTY && *right == EMPTY {
+        return EMPTY;
+    }
+    node_hash(left, right)
+}
+
+impl MerkleProof {
+    /// Whether the proof leads from the leaf `hash` to `root`.
+    pub fn verify(&self, hash: &Hash, root: &Hash) -> bool {
+        let computed = self.steps.iter().fold(leaf_hash(hash), |hash, step| {
+            if step.left {
+                node_hash(&step.sibling, &hash)
+            } else {
+                node_hash(&hash, &step.sibling)
+            }
+        });
+        computed == *root
+    }
+}
+
+impl InclusionProof {
+    /// Checks the proof against a header the caller already verified.
+    pub fn verify(&self, header: &BlockHeader) -> bool {
+        header.index == self.height
+            && header.get_hash() == self.block_hash
+            && self
+                .proof
+                .verify(&self.transaction.full_hash(), &header.merkle_root)
+    }
+}
+
+impl StateProof {
+    /// Whether the proof leads from `leaf`, the leaf of `address`, to `root`.
+    pub fn verify(&self, address: &Address, leaf: &Hash, root: &Hash) -> bool {
+        if self.siblings.len() != STATE_TREE_DEPTH {
+            return false;
+        }
+        let computed = (0..STATE_TREE_DEPTH).rev().zip(&self.siblings).fold(
+            *leaf,
+            |hash, (depth, sibling)| {
+                let sibling = sibling.unwrap_or(EMPTY);
+                if bit(address, depth) {
+                    sparse_node_hash(&sibling, &hash)
+                } else {
+                    sparse_node_hash(&hash, &sibling)
+                }
+            },
+        );
+        computed == *root
+    }
+}
+
+impl AccountProof {
+    /// Checks the proof against a header the caller already verified.
+    pub fn verify(&self, header: &BlockHeader) -> bool {
+        header.index == self.height
+            && header.get_hash() == self.block_hash
+            && self.proof.verify(
+                &self.state.address,
+                &account_leaf(&self.state),
+                &header.state_root,
+            )
+    }
+}
+
+fn leaf_hash(hash: &Hash) -> Hash {
+    digest(&[&[LEAF_PREFIX], hash])
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    digest(&[&[NODE_PREFIX], left, right])
+}
+
+fn digest(parts: &[&[u8]]) -> Hash {
+    let data = parts.concat();
+    let hash = hash(MessageDigest::sha3_256(), &data).unwrap();
+    let mut bytes: Hash = [0u8; 32];
+    bytes.copy_from_slice(&hash);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hashes(count: u8) -> Vec<Hash> {
+        (0..count).map(|i| [i; 32]).collect()
+    }
+
+    #[test]
+    fn proves_every_transaction() {
+        for count in 1..=9 {
+            let hashes = hashes(count);
+            let root = root(&hashes);
+            for (position, hash) in hashes.iter().enumerate() {
+                let proof = prove(&hashes, position).unwrap();
+                assert!(proof.verify(hash, &root), "{} of {}", position, count);
+                assert!(!proof.verify(&[0xff; 32], &root));
+            }
+            assert!(prove(&hashes, hashes.len()).is_none());
+        }
+    }
+
+    #[test]
+    fn root_commits_to_order() {
+        let mut hashes = hashes(4);
+        let original = root(&hashes);
+        hashes.swap(1, 2);
+        assert_ne!(root(&hashes), original);
+        assert_eq!(root(&[]), [0u8; 32]);
+    }
+
+    #[test]
+    fn leaf_is_not_a_node() {
+        // The root of two leaves must not verify as the single leaf of a one element tree.
+        let hashes = hashes(2);
+        let root = root(&hashes);
+        let combined = node_hash(&leaf_hash(&hashes[0]), &leaf_hash(&hashes[1]));
+        assert!(!MerkleProof { steps: Vec::new() }.verify(&combined, &root));
+    }
+
+    fn account(address: Address, balance: u128) -> AccountState {
+        AccountState {
+            balance,
+            ..AccountState::new(address)
+        }
+    }
+
+    /// Accounts whose addresses differ only in the last bits, at the first bit, and in between.
+    fn accounts() -> Vec<AccountState> {
+        let mut addresses = vec![[0u8; 16], [0u8; 16], [0x80; 16], [0x0f; 16]];
+        addresses[0][15] = 1;
+        addresses[1][15] = 2;
+        addresses.sort();
+        addresses
+            .into_iter()
+            .enumerate()
+            .map(|(i, address)| account(address, 10 + i as u128))
+            .collect()
+    }
+
+    fn leaves(accounts: &[AccountState]) -> Vec<Leaf> {
+        accounts
+            .iter()
+            .map(|state| (state.address, account_leaf(state)))
+            .collect()
+    }
+
+    #[test]
+    fn proves_every_account() {
+        let accounts = accounts();
+        let leaves = leaves(&accounts);
+        let root = state_root(&leaves);
+        for state in &accounts {
+            let proof = prove_state(&leaves, &state.address);
+            assert!(proof.verify(&state.address, &account_leaf(state), &root));
+
+            let changed = account(state.address, state.balance + 1);
+            assert!(!proof.verify(&state.address, &account_leaf(&changed), &root));
+            let unused = AccountState::new(state.address);
+            assert!(!proof.verify(&state.address, &account_leaf(&unused), &root));
+        }
+    }
+
+    #[test]
+    fn proves_an_absent_account() {
+        let accounts = accounts();
+        let leaves = leaves(&accounts);
+        let root = state_root(&leaves);
+        let mut absent = [0u8; 16];
+        absent[15] = 3;
+
+        let proof = prove_state(&leaves, &absent);
+        assert!(proof.verify(&absent, &account_leaf(&AccountState::new(absent)), &root));
+        assert!(!proof.verify(&absent, &account_leaf(&account(absent, 1)), &root));
+        // The proof of an absent account can't be passed off for a present one.
+        assert!(!proof.verify(&accounts[0].address, &EMPTY, &root));
+    }
+
+    #[test]
+    fn unused_accounts_are_empty_leaves() {
+        let address = [7u8; 16];
+        assert_eq!(account_leaf(&AccountState::new(address)), EMPTY);
+        assert_eq!(state_root(&[]), EMPTY);
+        // An explicit entry for an unused account doesn't change the root.
+        let accounts = accounts();
+        let mut leaves = leaves(&accounts);
+        let root = state_root(&leaves);
+        leaves.push((address, EMPTY));
+        leaves.sort();
+        assert_eq!(state_root(&leaves), root);
+    }
+
+    #[test]
+    fn rejects_proofs_of_the_wrong_depth() {
+        let accounts = accounts();
+        let leaves = leaves(&accounts);
+        let root = state_root(&leaves);
+        let mut proof = prove_state(&leaves, &accounts[0].address);
+        proof.siblings.pop();
+        assert!(!proof.verify(&accounts[0].address, &account_leaf(&accounts[0]), &root));
+    }
+}
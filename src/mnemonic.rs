@@ -0,0 +1,184 @@
+use openssl::{
+    hash::{hash, MessageDigest},
+    pkcs5::pbkdf2_hmac,
+    pkey::{Id, PKey, Private},
+    rand::rand_bytes,
+    sign::Signer,
+};
+
+use crate::error::{Error, KeyError, Result};
+
+/// The BIP-39 english word list.
+const WORDLIST: &str = include_str!("wordlist.txt");
+const ENTROPY_BYTES: usize = 32;
+const WORD_COUNT: usize = 24;
+const BITS_PER_WORD: usize = 11;
+const SEED_ITERATIONS: usize = 2048;
+const DERIVATION_KEY: &[u8] = b"zenchain/seed/v1";
+
+/// Generates a new random 24 word seed phrase.
+pub fn generate() -> Result<String> {
+    let mut entropy = [0u8; ENTROPY_BYTES];
+    rand_bytes(&mut entropy).map_err(crypto_error)?;
+    from_entropy(&entropy)
+}
+
+fn from_entropy(entropy: &[u8]) -> Result<String> {
+    let words = WORDLIST.lines().collect::<Vec<_>>();
+    let mut bits = entropy.to_vec();
+    bits.push(checksum(entropy)?);
+
+    let phrase = (0..WORD_COUNT)
+        .map(|word| {
+            let index = (word * BITS_PER_WORD..(word + 1) * BITS_PER_WORD).fold(0, |index, bit| {
+                (index << 1) | ((bits[bit / 8] >> (7 - bit % 8)) & 1) as usize
+            });
+            words[index]
+        })
+        .collect::<Vec<_>>();
+    Ok(phrase.join(" "))
+}
+
+/// Returns the entropy encoded by `phrase` after checking every word and the checksum.
+pub fn to_entropy(phrase: &str) -> Result<Vec<u8>> {
+    let words = WORDLIST.lines().collect::<Vec<_>>();
+    let indices = phrase
+        .split_whitespace()
+        .map(|word| {
+            let word = word.to_lowercase();
+            words
+                .iter()
+                .position(|w| *w == word)
+                .ok_or_else(|| invalid_mnemonic(format!("Unknown word '{}'", word)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if indices.len() != WORD_COUNT {
+        return Err(invalid_mnemonic(format!(
+            "Expected {} words, got {}",
+            WORD_COUNT,
+            indices.len()
+        )));
+    }
+
+    let mut bytes = vec![0u8; ENTROPY_BYTES + 1];
+    for (word, index) in indices.iter().enumerate() {
+        for i in 0..BITS_PER_WORD {
+            if (index >> (BITS_PER_WORD - 1 - i)) & 1 == 1 {
+                let bit = word * BITS_PER_WORD + i;
+                bytes[bit / 8] |= 1 << (7 - bit % 8);
+            }
+        }
+    }
+    let expected = bytes.pop().unwrap();
+    if checksum(&bytes)? != expected {
+        return Err(invalid_mnemonic("Invalid checksum".to_string()));
+    }
+    Ok(bytes)
+}
+
+/// Stretches a seed phrase into the 64 byte seed all wallet keys are derived from.
+pub fn to_seed(phrase: &str) -> Result<Vec<u8>> {
+    to_entropy(phrase)?;
+    let normalized = phrase
+        .split_whitespace()
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut seed = vec![0u8; 64];
+    pbkdf2_hmac(
+        normalized.as_bytes(),
+        b"mnemonic",
+        SEED_ITERATIONS,
+        MessageDigest::sha512(),
+        &mut seed,
+    )
+    .map_err(crypto_error)?;
+    Ok(seed)
+}
+
+/// Derives the key at `index` from a wallet seed. Derived keys are always Ed25519, since unlike
+/// RSA its private key is just 32 bytes that can be taken straight from the derivation.
+pub fn derive_key(seed: &[u8], index: u32) -> Result<PKey<Private>> {
+    let derived = PKey::hmac(DERIVATION_KEY)
+        .and_then(|key| {
+            let mut signer = Signer::new(MessageDigest::sha512(), &key)?;
+            signer.update(seed)?;
+            signer.update(&index.to_be_bytes())?;
+            signer.sign_to_vec()
+        })
+        .map_err(crypto_error)?;
+    PKey::private_key_from_raw_bytes(&derived[..32], Id::ED25519).map_err(crypto_error)
+}
+
+fn checksum(entropy: &[u8]) -> Result<u8> {
+    let digest = hash(MessageDigest::sha256(), entropy).map_err(crypto_error)?;
+    Ok(digest[0])
+}
+
+fn invalid_mnemonic(msg: String) -> Error {
+    Error::Key(KeyError::InvalidMnemonic(msg))
+}
+
+fn crypto_error(err: openssl::error::ErrorStack) -> Error {
+    Error::Key(KeyError::InvalidKey(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::{parse_hex, to_hex};
+
+    // 256-bit English test vectors of BIP-39. The seeds use an empty passphrase, so the salt is
+    // just "mnemonic".
+    const VECTORS: &[(&str, &str, &str)] = &[
+        (
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon art",
+            "408b285c123836004f4b8842c89324c1f01382450c0d439af345ba7fc49acf705489c6fc77dbd4e3dc1dd8cc6bc9f043db8ada1e243c4a0eafb290d399480840",
+        ),
+        (
+            "8080808080808080808080808080808080808080808080808080808080808080",
+            "letter advice cage absurd amount doctor acoustic avoid letter advice cage absurd amount doctor acoustic avoid letter advice cage absurd amount doctor acoustic bless",
+            "848bbe19cad445e46f35fd3d1a89463583ac2b60b5eb4cfcf955731775a5d9e17a81a71613fed83f1ae27b408478fdec2bbc75b5161d1937aa7cdf4ad686ef5f",
+        ),
+        (
+            "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+            "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo vote",
+            "e28a37058c7f5112ec9e16a3437cf363a2572d70b6ceb3b6965447623d620f14d06bb321a26b33ec15fcd84a3b5ddfd5520e230c924c87aaa0d559749e044fef",
+        ),
+        (
+            "68a79eaca2324873eacc50cb9c6eca8cc68ea5d936f98787c60c7ebc74e6ce7c",
+            "hamster diagram private dutch cause delay private meat slide toddler razor book happy fancy gospel tennis maple dilemma loan word shrug inflict delay length",
+            "17e4b5661796eeff8904550f8572289317ece7c1cc1316469f8f4c986c1ffd7b9f4c3aeac3e1713ffc21fa33707d09d57a2ece358d72111ef7c7658e7b33f2d5",
+        ),
+    ];
+
+    #[test]
+    fn test_vectors() {
+        for (entropy, phrase, seed) in VECTORS {
+            let entropy = parse_hex(entropy).unwrap();
+            assert_eq!(from_entropy(&entropy).unwrap(), *phrase);
+            assert_eq!(to_entropy(phrase).unwrap(), entropy);
+            assert_eq!(to_hex(&to_seed(phrase).unwrap()), *seed);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_checksum() {
+        let phrase = VECTORS[0].1.replace(" art", " abandon");
+        assert!(to_entropy(&phrase).is_err());
+        assert!(to_seed(&phrase).is_err());
+    }
+
+    #[test]
+    fn phrase_round_trip() {
+        let phrase = generate().unwrap();
+        assert_eq!(phrase.split_whitespace().count(), WORD_COUNT);
+        let entropy = to_entropy(&phrase).unwrap();
+        assert_eq!(from_entropy(&entropy).unwrap(), phrase);
+        assert_eq!(
+            to_seed(&phrase.to_uppercase()).unwrap(),
+            to_seed(&phrase).unwrap()
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use std::{fs, path::Path};
+
+use openssl::hash::{hash, MessageDigest};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, KeyError, Result},
+    keys::{self, ADDRESS_VERSION_MULTISIG},
+    types::{Address, PublicKey, TransactionSignature},
+};
+
+pub const MAX_MULTISIG_KEYS: usize = 16;
+
+/// An account controlled by `threshold` out of a set of keys.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MultisigPolicy {
+    pub threshold: u8,
+    pub public_keys: Vec<PublicKey>,
+}
+
+/// A signature by the key at `key_index` of a [`MultisigPolicy`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PartialSignature {
+    pub key_index: u8,
+    pub signature: TransactionSignature,
+}
+
+impl MultisigPolicy {
+    pub fn new(threshold: u8, public_keys: Vec<PublicKey>) -> Result<MultisigPolicy> {
+        let policy = MultisigPolicy {
+            threshold,
+            public_keys,
+        };
+        policy.check()?;
+        Ok(policy)
+    }
+
+    fn check(&self) -> Result<()> {
+        if self.public_keys.is_empty() || self.public_keys.len() > MAX_MULTISIG_KEYS {
+            return Err(invalid_policy(format!(
+                "Needs between 1 and {} keys",
+                MAX_MULTISIG_KEYS
+            )));
+        }
+        if self.threshold == 0 || self.threshold as usize > self.public_keys.len() {
+            return Err(invalid_policy(format!(
+                "Threshold must be between 1 and {}",
+                self.public_keys.len()
+            )));
+        }
+        for (i, key) in self.public_keys.iter().enumerate() {
+            keys::decode_public_key(key)?;
+            if self.public_keys[..i].contains(key) {
+                return Err(invalid_policy("Duplicate key".to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    /// The address is the hash of the version, threshold and every key in order, so the same
+    /// keys in a different order or with another threshold control a different account.
+    ///
+    /// Like every address it is truncated to 128 bits. That keeps addresses of all kinds the same
+    /// size, but a participant who picks their key after seeing the others' can search for a
+    /// second policy, controlled by them alone, with the same address in about 2^64 hashes.
+    /// Participants should therefore commit to their keys before learning the other keys.
+    pub fn address(&self) -> Address {
+        let mut data = vec![ADDRESS_VERSION_MULTISIG, self.threshold];
+        for key in &self.public_keys {
+            data.extend_from_slice(&(key.len() as u32).to_le_bytes());
+            data.extend_from_slice(key);
+        }
+        let hash = hash(MessageDigest::sha3_256(), &data).unwrap();
+        let mut address: Address = [0u8; 16];
+        address.copy_from_slice(&hash[0..16]);
+        address
+    }
+
+    pub fn key_index(&self, public_key: &PublicKey) -> Option<u8> {
+        self.public_keys
+            .iter()
+            .position(|key| key == public_key)
+            .map(|index| index as u8)
+    }
+
+    /// Checks that at least `threshold` distinct keys of the policy signed `data`. Signatures
+    /// must be sorted by key index without duplicates so there is one encoding per signer set.
+    pub fn is_satisfied(&self, data: &[u8], signatures: &[PartialSignature]) -> bool {
+        if self.check().is_err() || signatures.len() < self.threshold as usize {
+            return false;
+        }
+        let sorted = signatures
+            .windows(2)
+            .all(|pair| pair[0].key_index < pair[1].key_index);
+        sorted
+            && signatures.iter().all(|partial| {
+                match self.public_keys.get(partial.key_index as usize) {
+                    Some(key) => keys::verify(key, data, &partial.signature),
+                    None => false,
+                }
+            })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let data = bincode::serialize(self).map_err(|e| Error::Storage(e.to_string()))?;
+        fs::write(path, data).map_err(|e| Error::Storage(e.to_string()))
+    }
+
+    pub fn load(path: &Path) -> Result<MultisigPolicy> {
+        let data = fs::read(path).map_err(|e| Error::Storage(e.to_string()))?;
+        let policy: MultisigPolicy = bincode::deserialize(&data).map_err(|_| {
+            Error::Storage(format!("{} is not a multisig policy file", path.display()))
+        })?;
+        policy.check()?;
+        Ok(policy)
+    }
+}
+
+fn invalid_policy(msg: String) -> Error {
+    Error::Key(KeyError::InvalidKey(format!(
+        "Invalid multisig policy: {}",
+        msg
+    )))
+}
@@ -0,0 +1,329 @@
+use std::fmt::Display;
+
+use openssl::hash::{hash, MessageDigest};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result, ValidationError},
+    keys::{self, ADDRESS_VERSION_SCRIPT},
+    types::{Address, PublicKey},
+};
+
+pub const MAX_SCRIPT_OPS: usize = 256;
+pub const MAX_STACK_SIZE: usize = 256;
+pub const MAX_ELEMENT_SIZE: usize = 1024;
+/// Most [`Op::CheckSig`] ops a script may contain, since signature checks are by far the most
+/// expensive ops to evaluate.
+pub const MAX_CHECKSIG_OPS: usize = 16;
+
+/// A spending condition. The witness items of the spending transaction are pushed onto the
+/// stack in order, then the ops run. The script succeeds if it leaves exactly one true item.
+///
+/// Evaluation only depends on the script, the witness and the transaction, never on the chain,
+/// so a transaction that passes once passes everywhere.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Script {
+    pub ops: Vec<Op>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Op {
+    Push(Vec<u8>),
+    Dup,
+    Drop,
+    Swap,
+    /// Replaces the top item with its sha3-256 hash.
+    Sha3,
+    Equal,
+    /// Fails the script unless the top item is true, removing it.
+    Verify,
+    /// Pops a public key and a signature and pushes whether the signature signs the transaction.
+    CheckSig,
+    /// True if the transaction can't be mined before this height, see `Transaction::not_before`.
+    After(u128),
+    /// True if the transaction must be mined before this height, see `Transaction::valid_until`.
+    Before(u128),
+    And,
+    Or,
+    Not,
+}
+
+/// What a script can see of the transaction that spends from it.
+pub struct ScriptContext<'a> {
+    /// The signed data of the transaction, checked by [`Op::CheckSig`].
+    pub data: &'a [u8],
+    pub not_before: Option<u128>,
+    pub valid_until: Option<u128>,
+}
+
+const TRUE: &[u8] = &[1];
+const FALSE: &[u8] = &[];
+
+impl Script {
+    /// Parses the text form, e.g. `SHA3 0x1234 EQUAL VERIFY 0xabcd CHECKSIG`. Data is pushed as
+    /// `0x` prefixed hex, heights follow `AFTER` and `BEFORE`.
+    pub fn parse(text: &str) -> Result<Script> {
+        let mut ops = Vec::new();
+        let mut words = text.split_whitespace();
+        while let Some(word) = words.next() {
+            let op = match word.to_uppercase().as_str() {
+                "DUP" => Op::Dup,
+                "DROP" => Op::Drop,
+                "SWAP" => Op::Swap,
+                "SHA3" => Op::Sha3,
+                "EQUAL" => Op::Equal,
+                "VERIFY" => Op::Verify,
+                "CHECKSIG" => Op::CheckSig,
+                "AND" => Op::And,
+                "OR" => Op::Or,
+                "NOT" => Op::Not,
+                "AFTER" | "BEFORE" => {
+                    let height = words
+                        .next()
+                        .and_then(|height| height.parse().ok())
+                        .ok_or_else(|| invalid_script(format!("{} needs a height", word)))?;
+                    match word.to_uppercase().as_str() {
+                        "AFTER" => Op::After(height),
+                        _ => Op::Before(height),
+                    }
+                }
+                _ => match word.strip_prefix("0x") {
+                    Some(hex) => Op::Push(
+                        parse_hex(hex)
+                            .ok_or_else(|| invalid_script(format!("Invalid data '{}'", word)))?,
+                    ),
+                    None => return Err(invalid_script(format!("Unknown op '{}'", word))),
+                },
+            };
+            ops.push(op);
+        }
+        let script = Script { ops };
+        script.check()?;
+        Ok(script)
+    }
+
+    pub fn check(&self) -> Result<()> {
+        if self.ops.is_empty() || self.ops.len() > MAX_SCRIPT_OPS {
+            return Err(invalid_script(format!(
+                "Needs between 1 and {} ops",
+                MAX_SCRIPT_OPS
+            )));
+        }
+        for op in &self.ops {
+            if let Op::Push(data) = op {
+                if data.len() > MAX_ELEMENT_SIZE {
+                    return Err(invalid_script(format!(
+                        "Data is longer than {} bytes",
+                        MAX_ELEMENT_SIZE
+                    )));
+                }
+            }
+        }
+        if self.ops.iter().filter(|op| **op == Op::CheckSig).count() > MAX_CHECKSIG_OPS {
+            return Err(invalid_script(format!(
+                "Has more than {} CHECKSIG ops",
+                MAX_CHECKSIG_OPS
+            )));
+        }
+        Ok(())
+    }
+
+    /// The address of the account locked by this script. It is truncated to 128 bits, with the
+    /// collision caveat described at [`MultisigPolicy::address`] for scripts several parties
+    /// contribute to.
+    ///
+    /// [`MultisigPolicy::address`]: crate::multisig::MultisigPolicy::address
+    pub fn address(&self) -> Address {
+        let mut data = vec![ADDRESS_VERSION_SCRIPT];
+        data.extend_from_slice(&bincode::serialize(self).unwrap());
+        let hash = hash(MessageDigest::sha3_256(), &data).unwrap();
+        let mut address: Address = [0u8; 16];
+        address.copy_from_slice(&hash[0..16]);
+        address
+    }
+
+    /// Runs the script against `witness`. Any malformed script or witness evaluates to false.
+    pub fn eval(&self, witness: &[Vec<u8>], context: &ScriptContext) -> bool {
+        if self.check().is_err()
+            || witness.len() > MAX_STACK_SIZE
+            || witness.iter().any(|item| item.len() > MAX_ELEMENT_SIZE)
+        {
+            return false;
+        }
+        let mut stack = witness.to_vec();
+        for op in &self.ops {
+            if self.step(op, &mut stack, context).is_none() || stack.len() > MAX_STACK_SIZE {
+                return false;
+            }
+        }
+        stack.len() == 1 && is_true(&stack[0])
+    }
+
+    /// Applies one op, or returns `None` if the script fails.
+    fn step(&self, op: &Op, stack: &mut Vec<Vec<u8>>, context: &ScriptContext) -> Option<()> {
+        let push_bool = |stack: &mut Vec<Vec<u8>>, value: bool| {
+            stack.push(if value { TRUE } else { FALSE }.to_vec())
+        };
+        match op {
+            Op::Push(data) => stack.push(data.clone()),
+            Op::Dup => stack.push(stack.last()?.clone()),
+            Op::Drop => {
+                stack.pop()?;
+            }
+            Op::Swap => {
+                let len = stack.len();
+                if len < 2 {
+                    return None;
+                }
+                stack.swap(len - 1, len - 2);
+            }
+            Op::Sha3 => {
+                let item = stack.pop()?;
+                stack.push(hash(MessageDigest::sha3_256(), &item).ok()?.to_vec());
+            }
+            Op::Equal => {
+                let (a, b) = (stack.pop()?, stack.pop()?);
+                push_bool(stack, a == b);
+            }
+            Op::Verify => {
+                if !is_true(&stack.pop()?) {
+                    return None;
+                }
+            }
+            Op::CheckSig => {
+                let public_key: PublicKey = stack.pop()?;
+                let signature = stack.pop()?;
+                push_bool(stack, keys::verify(&public_key, context.data, &signature));
+            }
+            Op::After(height) => {
+                push_bool(stack, context.not_before.is_some_and(|h| h >= *height));
+            }
+            Op::Before(height) => {
+                push_bool(stack, context.valid_until.is_some_and(|h| h < *height));
+            }
+            Op::And => {
+                let (a, b) = (stack.pop()?, stack.pop()?);
+                push_bool(stack, is_true(&a) && is_true(&b));
+            }
+            Op::Or => {
+                let (a, b) = (stack.pop()?, stack.pop()?);
+                push_bool(stack, is_true(&a) || is_true(&b));
+            }
+            Op::Not => {
+                let item = stack.pop()?;
+                push_bool(stack, !is_true(&item));
+            }
+        }
+        Some(())
+    }
+}
+
+impl Display for Script {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let words = self
+            .ops
+            .iter()
+            .map(|op| match op {
+                Op::Push(data) => format!("0x{}", to_hex(data)),
+                Op::Dup => "DUP".to_string(),
+                Op::Drop => "DROP".to_string(),
+                Op::Swap => "SWAP".to_string(),
+                Op::Sha3 => "SHA3".to_string(),
+                Op::Equal => "EQUAL".to_string(),
+                Op::Verify => "VERIFY".to_string(),
+                Op::CheckSig => "CHECKSIG".to_string(),
+                Op::After(height) => format!("AFTER {}", height),
+                Op::Before(height) => format!("BEFORE {}", height),
+                Op::And => "AND".to_string(),
+                Op::Or => "OR".to_string(),
+                Op::Not => "NOT".to_string(),
+            })
+            .collect::<Vec<_>>();
+        write!(f, "{}", words.join(" "))
+    }
+}
+
+/// Any item with a non-zero byte is true, so both `[]` and `[0]` are false.
+fn is_true(item: &[u8]) -> bool {
+    item.iter().any(|b| *b != 0)
+}
+
+pub fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn invalid_script(msg: String) -> Error {
+    ValidationError::InvalidScript(msg).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::pkey::PKey;
+
+    use super::*;
+
+    const DATA: &[u8] = b"signed data of the spending transaction";
+
+    fn context(not_before: Option<u128>, valid_until: Option<u128>) -> ScriptContext<'static> {
+        ScriptContext {
+            data: DATA,
+            not_before,
+            valid_until,
+        }
+    }
+
+    #[test]
+    fn hashlock() {
+        let hash = hash(MessageDigest::sha3_256(), b"secret").unwrap();
+        let script = Script::parse(&format!("SHA3 0x{} EQUAL", to_hex(&hash))).unwrap();
+        let context = context(None, None);
+        assert!(script.eval(&[b"secret".to_vec()], &context));
+        assert!(!script.eval(&[b"guess".to_vec()], &context));
+        assert!(!script.eval(&[], &context));
+        assert!(!script.eval(&[b"secret".to_vec(), b"secret".to_vec()], &context));
+    }
+
+    #[test]
+    fn timelock() {
+        let after = Script::parse("AFTER 100").unwrap();
+        assert!(after.eval(&[], &context(Some(100), None)));
+        assert!(!after.eval(&[], &context(Some(99), None)));
+        assert!(!after.eval(&[], &context(None, None)));
+
+        let before = Script::parse("BEFORE 100").unwrap();
+        assert!(before.eval(&[], &context(None, Some(99))));
+        assert!(!before.eval(&[], &context(None, Some(100))));
+        assert!(!before.eval(&[], &context(None, None)));
+    }
+
+    #[test]
+    fn checksig() {
+        let key = PKey::generate_ed25519().unwrap();
+        let other = PKey::generate_ed25519().unwrap();
+        let public_key = keys::encode_public_key(&key).unwrap();
+        let script = Script::parse(&format!("0x{} CHECKSIG", to_hex(&public_key))).unwrap();
+        let context = context(None, None);
+
+        assert!(script.eval(&[keys::sign(&key, DATA).unwrap()], &context));
+        assert!(!script.eval(&[keys::sign(&key, b"other data").unwrap()], &context));
+        assert!(!script.eval(&[keys::sign(&other, DATA).unwrap()], &context));
+        assert!(!script.eval(&[], &context));
+    }
+
+    #[test]
+    fn limits_checksig_ops() {
+        let checksig = |count| vec!["0x01 0x02 CHECKSIG"; count].join(" ");
+        assert!(Script::parse(&checksig(MAX_CHECKSIG_OPS)).is_ok());
+        assert!(Script::parse(&checksig(MAX_CHECKSIG_OPS + 1)).is_err());
+    }
+}
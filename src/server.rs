@@ -1,12 +1,15 @@
 use std::{
-    fs,
     io::{Read, Write},
     net::{TcpListener, TcpStream},
     sync::mpsc::{Receiver, Sender},
     thread,
 };
 
-use crate::types::{ClientNetworkMessage, ServerNetworkMessage};
+use crate::{
+    config,
+    error::{Error, Result},
+    types::{ClientNetworkMessage, ServerNetworkMessage},
+};
 
 pub struct BlockchainServer {}
 
@@ -17,16 +20,29 @@ impl BlockchainServer {
         on_return: Receiver<ClientNetworkMessage>,
     ) {
         thread::spawn(move || {
-            let listener = TcpListener::bind(format!("0.0.0.0:{}", port)).unwrap();
+            let listener = TcpListener::bind(config::get().listen_address(port)).unwrap();
             println!("Running Blockchain Server on port {}", port);
 
             for stream in listener.incoming() {
                 match stream {
                     Ok(mut stream) => {
-                        let message = BlockchainServer::read_message(&mut stream);
-                        on_message.send(message).unwrap();
-                        let return_message = on_return.recv().unwrap();
-                        BlockchainServer::write_message(&mut stream, return_message);
+                        let return_message = match BlockchainServer::read_message(&mut stream) {
+                            Ok(message) if message.is_admin() && !is_local(&stream) => {
+                                ClientNetworkMessage::Error(Error::Network(
+                                    "Miner control is only accepted from localhost".to_string(),
+                                ))
+                            }
+                            Ok(message) => {
+                                on_message.send(message).unwrap();
+                                on_return.recv().unwrap()
+                            }
+                            Err(err) => ClientNetworkMessage::Error(err),
+                        };
+                        if let Err(err) =
+                            BlockchainServer::write_message(&mut stream, return_message)
+                        {
+                            println!("Server Error: {}", err);
+                        }
                     }
                     Err(e) => {
                         println!("Server Error: {}", e);
@@ -38,30 +54,36 @@ impl BlockchainServer {
         });
     }
 
-    fn read_message(stream: &mut TcpStream) -> ServerNetworkMessage {
+    fn read_message(stream: &mut TcpStream) -> Result<ServerNetworkMessage> {
         let mut len_buffer = [0u8; 4];
-        stream.read_exact(&mut len_buffer).unwrap();
+        stream.read_exact(&mut len_buffer).map_err(network_error)?;
         let len = u32::from_le_bytes(len_buffer);
         let mut buffer: Vec<u8> = vec![0u8; len as usize];
-        stream.read_exact(&mut buffer).unwrap();
-        let message = bincode::deserialize::<ServerNetworkMessage>(&buffer).unwrap();
-        return message;
+        stream.read_exact(&mut buffer).map_err(network_error)?;
+        let message = bincode::deserialize::<ServerNetworkMessage>(&buffer)
+            .map_err(|e| Error::Network(e.to_string()))?;
+        return Ok(message);
     }
 
-    fn write_message(stream: &mut TcpStream, message: ClientNetworkMessage) {
-        let buffer = bincode::serialize(&message).unwrap();
+    fn write_message(stream: &mut TcpStream, message: ClientNetworkMessage) -> Result<()> {
+        let buffer = bincode::serialize(&message).map_err(|e| Error::Network(e.to_string()))?;
         let len_bytes = (buffer.len() as u32).to_le_bytes();
-        stream.write(&len_bytes).unwrap();
-        stream.write(&buffer).unwrap();
-        stream.flush().unwrap();
+        stream.write_all(&len_bytes).map_err(network_error)?;
+        stream.write_all(&buffer).map_err(network_error)?;
+        stream.flush().map_err(network_error)
     }
 }
 
 pub fn load_nodes() -> Vec<String> {
-    let mut nodes = Vec::new();
-    let nodes_file = fs::read_to_string("nodes.txt").unwrap();
-    for line in nodes_file.lines() {
-        nodes.push(line.to_string());
-    }
-    nodes
+    config::get().peers.clone()
+}
+
+fn is_local(stream: &TcpStream) -> bool {
+    stream
+        .peer_addr()
+        .is_ok_and(|address| address.ip().to_canonical().is_loopback())
+}
+
+fn network_error(err: std::io::Error) -> Error {
+    Error::Network(err.to_string())
 }
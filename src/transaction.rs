@@ -1,133 +1,481 @@
-use std::fmt::Display;
+use std::{fmt::Display, fs, path::Path, slice};
 
 use crate::{
     blockchain::World,
     client::BlockchainClient,
+    config,
+    error::{Error, KeyError, Result, ValidationError},
     keys,
-    types::{Address, PublicKey, ServerNetworkMessage, TransactionData, TransactionSignature},
+    multisig::{MultisigPolicy, PartialSignature},
+    script::{Script, ScriptContext},
+    types::{
+        Address, ClientNetworkMessage, Hash, PublicKey, ServerNetworkMessage, TransactionData,
+        TransactionSignature,
+    },
 };
 
 use openssl::{
-    hash::MessageDigest,
+    hash::{hash, MessageDigest},
     memcmp,
-    pkey::PKey,
-    rsa::Rsa,
-    sign::{Signer, Verifier},
+    pkey::{PKey, Private},
 };
 use serde::{Deserialize, Serialize};
-use serde_big_array::BigArray;
+
+/// Prefixed to the signed data so a transaction signature can't be mistaken for any other message.
+const SIGNING_DOMAIN: &[u8] = b"zenchain/transaction/v1";
+/// Signing domain of batch payments, which are laid out differently.
+const BATCH_SIGNING_DOMAIN: &[u8] = b"zenchain/batch-transaction/v1";
+pub const MAX_BATCH_OUTPUTS: usize = 1000;
+pub const MAX_MEMO_LEN: usize = 256;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Transaction {
-    pub amount: u128,
+    pub chain_id: u32,
     pub index: u128,
     pub sender: Address,
+    pub payment: Payment,
+    /// Paid to the miner of the block that includes the transaction.
+    pub fee: u128,
+    /// First block height the transaction may be included at, if it is time-locked.
+    pub not_before: Option<u128>,
+    /// Last block height the transaction may be included at. Pending transactions are dropped
+    /// after that, so the sender can reuse the index.
+    pub valid_until: Option<u128>,
+    /// Free-form reference for the recipient, e.g. an exchange deposit id.
+    pub memo: Option<Vec<u8>>,
+
+    pub authorization: Authorization,
+}
+
+/// Where the funds of a transaction go.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Payment {
+    Single(Output),
+    /// Pays several recipients at once, all or nothing, under one signature.
+    Batch(Vec<Output>),
+}
+
+impl Payment {
+    pub fn batch(outputs: Vec<Output>) -> Result<Payment> {
+        let payment = Payment::Batch(outputs);
+        payment.check()?;
+        Ok(payment)
+    }
+
+    fn check(&self) -> Result<()> {
+        if let Payment::Batch(outputs) = self {
+            if outputs.is_empty() || outputs.len() > MAX_BATCH_OUTPUTS {
+                return Err(ValidationError::InvalidOutputs(format!(
+                    "A batch needs between 1 and {} outputs",
+                    MAX_BATCH_OUTPUTS
+                ))
+                .into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Output {
     pub recipient: Address,
+    pub amount: u128,
+}
 
-    #[serde(with = "BigArray")]
-    pub signature: TransactionSignature,
-    #[serde(with = "BigArray")]
-    pub public_key: PublicKey,
+/// Proof that the sender approved a transaction.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Authorization {
+    /// Not signed yet.
+    None,
+    Single {
+        public_key: PublicKey,
+        signature: TransactionSignature,
+    },
+    Multisig {
+        policy: MultisigPolicy,
+        signatures: Vec<PartialSignature>,
+    },
+    /// Spends from a script address. The witness items are the script's input.
+    Script {
+        script: Script,
+        witness: Vec<Vec<u8>>,
+    },
 }
 
 impl Transaction {
-    pub fn is_signature_valid(&self) -> bool {
-        let data = Transaction::transaction_data_bytes(
-            &self.sender,
-            &self.recipient,
-            self.amount,
-            self.index,
-        );
-        let rsa = Rsa::public_key_from_der(&self.public_key).unwrap();
-        let pkey = PKey::from_rsa(rsa.clone()).unwrap();
+    /// Creates an unsigned transaction. It needs no private key, so it can be prepared on an
+    /// online machine and handed to an offline one for [`Transaction::sign`].
+    pub fn new(sender: Address, recipient: Address, amount: u128, index: u128) -> Transaction {
+        Transaction::with_payment(sender, Payment::Single(Output { recipient, amount }), index)
+    }
 
-        let mut verifier = Verifier::new(MessageDigest::sha3_256(), &pkey).unwrap();
-        verifier.update(&data).unwrap();
-        let valid = verifier.verify(&self.signature).unwrap();
+    pub fn with_payment(sender: Address, payment: Payment, index: u128) -> Transaction {
+        Transaction {
+            chain_id: config::get().network.chain_id(),
+            index,
+            sender,
+            payment,
+            fee: 0,
+            not_before: None,
+            valid_until: None,
+            memo: None,
+            authorization: Authorization::None,
+        }
+    }
 
-        let address = keys::keypair_to_address(&rsa);
-        let is_sender = memcmp::eq(&address, &self.sender);
+    /// Creates an unsigned transaction from a multisig account. Signatures are added one at a
+    /// time with [`Transaction::sign`] and can be merged with [`Transaction::combine`].
+    pub fn new_multisig(policy: MultisigPolicy, payment: Payment, index: u128) -> Transaction {
+        let mut transaction = Transaction::with_payment(policy.address(), payment, index);
+        transaction.authorization = Authorization::Multisig {
+            policy,
+            signatures: Vec::new(),
+        };
+        transaction
+    }
 
-        return valid && is_sender;
+    /// Creates a transaction spending from the account locked by `script`. Its witness is built
+    /// with [`Transaction::sign`] and [`Transaction::add_witness`].
+    pub fn new_script(script: Script, payment: Payment, index: u128) -> Transaction {
+        let mut transaction = Transaction::with_payment(script.address(), payment, index);
+        transaction.authorization = Authorization::Script {
+            script,
+            witness: Vec::new(),
+        };
+        transaction
     }
 
-    pub fn is_valid(&self, account_states: &World) -> Result<(), String> {
-        if !self.is_signature_valid() {
-            return Err("Invalid signature".to_string());
+    /// Pushes a data item, e.g. a hash preimage, onto the witness of a script transaction.
+    pub fn add_witness(&mut self, item: Vec<u8>) -> Result<()> {
+        match self.authorization {
+            Authorization::Script {
+                ref mut witness, ..
+            } => {
+                witness.push(item);
+                Ok(())
+            }
+            _ => Err(KeyError::InvalidKey("Not a script transaction".to_string()).into()),
+        }
+    }
+
+    /// Signs as the sender, or adds a partial signature if the sender is a multisig account.
+    /// For script transactions the signature is pushed onto the witness.
+    pub fn sign(&mut self, key: &PKey<Private>) -> Result<()> {
+        let public_key = keys::encode_public_key(key)?;
+        let signature = keys::sign(key, &self.signing_data())?;
+        match self.authorization {
+            Authorization::Multisig {
+                ref policy,
+                ref mut signatures,
+            } => {
+                let key_index = policy.key_index(&public_key).ok_or_else(|| {
+                    KeyError::InvalidKey("Key is not part of the multisig policy".to_string())
+                })?;
+                signatures.retain(|partial| partial.key_index != key_index);
+                signatures.push(PartialSignature {
+                    key_index,
+                    signature,
+                });
+                signatures.sort_by_key(|partial| partial.key_index);
+            }
+            Authorization::Script {
+                ref mut witness, ..
+            } => witness.push(signature),
+            _ => {
+                if keys::keypair_to_address(key)? != self.sender {
+                    return Err(KeyError::InvalidKey(format!(
+                        "Key doesn't belong to sender {}",
+                        keys::format_address(&self.sender)
+                    ))
+                    .into());
+                }
+                self.authorization = Authorization::Single {
+                    public_key,
+                    signature,
+                };
+            }
+        }
+        Ok(())
+    }
+
+    /// Merges the partial signatures of copies of the same multisig transaction.
+    pub fn combine(&mut self, other: &Transaction) -> Result<()> {
+        let mismatch = || Error::Validation(ValidationError::TransactionMismatch);
+        if self.signing_data() != other.signing_data() {
+            return Err(mismatch());
+        }
+        match (&mut self.authorization, &other.authorization) {
+            (
+                Authorization::Multisig { policy, signatures },
+                Authorization::Multisig {
+                    policy: other_policy,
+                    signatures: other_signatures,
+                },
+            ) if policy == other_policy => {
+                for partial in other_signatures {
+                    if !signatures.iter().any(|s| s.key_index == partial.key_index) {
+                        signatures.push(partial.clone());
+                    }
+                }
+                signatures.sort_by_key(|partial| partial.key_index);
+                Ok(())
+            }
+            _ => Err(mismatch()),
+        }
+    }
+
+    pub fn is_signed(&self) -> bool {
+        match &self.authorization {
+            Authorization::None => false,
+            Authorization::Single { signature, .. } => !signature.is_empty(),
+            Authorization::Multisig { policy, signatures } => {
+                signatures.len() >= policy.threshold as usize
+            }
+            Authorization::Script { .. } => true,
+        }
+    }
+
+    pub fn is_signature_valid(&self) -> bool {
+        let data = self.signing_data();
+        match &self.authorization {
+            Authorization::None => false,
+            Authorization::Single {
+                public_key,
+                signature,
+            } => {
+                let valid = keys::verify(public_key, &data, signature);
+
+                let is_sender = match keys::public_key_to_address(public_key) {
+                    Ok(address) => memcmp::eq(&address, &self.sender),
+                    Err(_) => false,
+                };
+
+                valid && is_sender
+            }
+            Authorization::Multisig { policy, signatures } => {
+                memcmp::eq(&policy.address(), &self.sender)
+                    && policy.is_satisfied(&data, signatures)
+            }
+            Authorization::Script { script, witness } => {
+                let context = ScriptContext {
+                    data: &data,
+                    not_before: self.not_before,
+                    valid_until: self.valid_until,
+                };
+                memcmp::eq(&script.address(), &self.sender) && script.eval(witness, &context)
+            }
         }
+    }
+
+    pub fn is_valid(&self, account_states: &World) -> Result<()> {
+        self.is_well_formed()?;
+        let amount = self
+            .total_amount()
+            .and_then(|total| total.checked_add(self.fee))
+            .ok_or_else(|| ValidationError::InvalidAmount("Total amount overflows".to_string()))?;
         let account_state = account_states.get_account_state(&self.sender);
-        if account_state.balance < self.amount {
-            return Err("Insufficient balance".to_string());
+        if account_state.balance < amount {
+            return Err(ValidationError::InsufficientBalance {
+                balance: account_state.balance,
+                amount,
+            }
+            .into());
         }
         if account_state.transaction_index + 1 != self.index {
-            return Err("Invalid transaction index".to_string());
+            return Err(ValidationError::InvalidTransactionIndex {
+                expected: account_state.transaction_index + 1,
+                actual: self.index,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// The checks of [`Transaction::is_valid`] that don't depend on the account state.
+    pub fn is_well_formed(&self) -> Result<()> {
+        let chain_id = config::get().network.chain_id();
+        if self.chain_id != chain_id {
+            return Err(ValidationError::InvalidChainId {
+                expected: chain_id,
+                actual: self.chain_id,
+            }
+            .into());
+        }
+        if !self.is_signature_valid() {
+            return Err(ValidationError::InvalidSignature.into());
         }
-        return Ok(());
+        self.payment.check()?;
+        self.check_memo()
     }
 
-    pub fn send(to: &str, amount: u128, client: &BlockchainClient) -> Result<(), String> {
-        let rsa = keys::load_keypair(None);
-        let sender = keys::keypair_to_address(&rsa);
-        let recipient = keys::parse_address(&to);
+    pub fn outputs(&self) -> &[Output] {
+        match &self.payment {
+            Payment::Single(output) => slice::from_ref(output),
+            Payment::Batch(outputs) => outputs,
+        }
+    }
 
-        let private_key = PKey::from_rsa(rsa).unwrap();
+    /// Sum of all outputs, or `None` if it overflows.
+    pub fn total_amount(&self) -> Option<u128> {
+        self.outputs()
+            .iter()
+            .try_fold(0u128, |total, output| total.checked_add(output.amount))
+    }
 
-        let state = client.account_state(sender)?;
-        let index = state.transaction_index + 1;
+    pub fn check_memo(&self) -> Result<()> {
+        match self.memo {
+            Some(ref memo) if memo.len() > MAX_MEMO_LEN => Err(ValidationError::MemoTooLong {
+                length: memo.len(),
+                max: MAX_MEMO_LEN,
+            }
+            .into()),
+            _ => Ok(()),
+        }
+    }
 
-        let transaction_data =
-            Transaction::transaction_data_bytes(&sender, &recipient, amount, index);
+    /// The memo as text if it is valid UTF-8, hex encoded otherwise.
+    pub fn memo_text(&self) -> Option<String> {
+        let memo = self.memo.as_ref()?;
+        Some(match std::str::from_utf8(memo) {
+            Ok(text) => text.to_string(),
+            Err(_) => format!(
+                "0x{}",
+                memo.iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<String>()
+            ),
+        })
+    }
 
-        let mut signer = Signer::new(MessageDigest::sha3_256(), &private_key).unwrap();
-        signer.update(&transaction_data).unwrap();
-        let signature = signer.sign_to_vec().unwrap();
+    /// Checks that the transaction can be included in a block at `height`, i.e. it is neither
+    /// time-locked beyond it nor expired.
+    pub fn is_valid_at(&self, height: u128) -> Result<()> {
+        if let Some(valid_until) = self.valid_until {
+            if self.is_expired(height) {
+                return Err(ValidationError::Expired {
+                    valid_until,
+                    height,
+                }
+                .into());
+            }
+        }
+        if let Some(not_before) = self.not_before {
+            if height < not_before {
+                return Err(ValidationError::TimeLocked { not_before, height }.into());
+            }
+        }
+        Ok(())
+    }
 
-        let mut signature_hash: TransactionSignature = [0u8; 256];
-        signature_hash.copy_from_slice(&signature[..256]);
+    pub fn is_expired(&self, height: u128) -> bool {
+        self.valid_until
+            .is_some_and(|valid_until| height > valid_until)
+    }
 
-        let public_key_vec = private_key.public_key_to_der().unwrap();
-        let mut public_key: PublicKey = [0u8; 294];
-        public_key.copy_from_slice(&public_key_vec[..294]);
+    /// Identifies the transaction: the hash of the signed data, so it changes when the
+    /// transaction is re-signed with a higher fee, but not when anyone re-encodes its
+    /// signatures or witness.
+    pub fn id(&self) -> Hash {
+        sha3(&self.signing_data())
+    }
 
-        let transaction = Transaction {
-            amount,
-            index,
-            public_key,
-            recipient,
-            sender,
-            signature: signature_hash,
-        };
+    /// Hash of the whole transaction including its authorization, which the block's Merkle
+    /// root commits to.
+    pub fn full_hash(&self) -> Hash {
+        sha3(&bincode::serialize(self).unwrap())
+    }
 
-        match client.send(ServerNetworkMessage::SubmitTransaction(transaction))? {
-            crate::types::ClientNetworkMessage::Ack => Ok(()),
-            crate::types::ClientNetworkMessage::Error(msg) => Err(msg),
-            _ => Err("Unexpected response from server".to_string()),
+    pub fn submit(&self, client: &BlockchainClient) -> Result<()> {
+        match client.send(ServerNetworkMessage::SubmitTransaction(self.clone()))? {
+            ClientNetworkMessage::Ack => Ok(()),
+            ClientNetworkMessage::Error(err) => Err(err),
+            msg => Err(Error::Network(format!("Unexpected response: {:?}", msg))),
         }
     }
 
-    fn transaction_data_bytes(
-        from: &Address,
-        to: &Address,
-        amount: u128,
-        index: u128,
-    ) -> TransactionData {
-        let mut data = [0u8; 64];
-        data[0..16].copy_from_slice(&from[..]);
-        data[16..32].copy_from_slice(&to[..]);
-        data[32..48].copy_from_slice(&amount.to_le_bytes());
-        data[48..64].copy_from_slice(&index.to_le_bytes());
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let data = bincode::serialize(self).map_err(|e| Error::Storage(e.to_string()))?;
+        fs::write(path, data).map_err(|e| Error::Storage(e.to_string()))
+    }
+
+    pub fn load(path: &Path) -> Result<Transaction> {
+        let data = fs::read(path).map_err(|e| Error::Storage(e.to_string()))?;
+        bincode::deserialize(&data)
+            .map_err(|_| Error::Storage(format!("{} is not a transaction file", path.display())))
+    }
+
+    fn signing_data(&self) -> TransactionData {
+        let mut data = Vec::with_capacity(BATCH_SIGNING_DOMAIN.len() + 68);
+        match &self.payment {
+            Payment::Single(output) => {
+                data.extend_from_slice(SIGNING_DOMAIN);
+                data.extend_from_slice(&self.chain_id.to_le_bytes());
+                data.extend_from_slice(&self.sender[..]);
+                data.extend_from_slice(&output.recipient[..]);
+                data.extend_from_slice(&output.amount.to_le_bytes());
+                data.extend_from_slice(&self.index.to_le_bytes());
+                data.extend_from_slice(&self.fee.to_le_bytes());
+            }
+            Payment::Batch(outputs) => {
+                data.extend_from_slice(BATCH_SIGNING_DOMAIN);
+                data.extend_from_slice(&self.chain_id.to_le_bytes());
+                data.extend_from_slice(&self.sender[..]);
+                data.extend_from_slice(&self.index.to_le_bytes());
+                data.extend_from_slice(&self.fee.to_le_bytes());
+                data.extend_from_slice(&(outputs.len() as u32).to_le_bytes());
+                for output in outputs {
+                    data.extend_from_slice(&output.recipient[..]);
+                    data.extend_from_slice(&output.amount.to_le_bytes());
+                }
+            }
+        }
+        for (tag, height) in [(0u8, self.not_before), (1u8, self.valid_until)] {
+            if let Some(height) = height {
+                data.push(tag);
+                data.extend_from_slice(&height.to_le_bytes());
+            }
+        }
+        if let Some(ref memo) = self.memo {
+            data.push(2);
+            data.extend_from_slice(&(memo.len() as u32).to_le_bytes());
+            data.extend_from_slice(memo);
+        }
         data
     }
 }
 
+fn sha3(data: &[u8]) -> Hash {
+    let hash = hash(MessageDigest::sha3_256(), data).unwrap();
+    let mut bytes: Hash = [0u8; 32];
+    bytes.copy_from_slice(&hash);
+    bytes
+}
+
 impl Display for Transaction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let recipient = match &self.payment {
+            Payment::Single(output) => keys::format_address(&output.recipient),
+            Payment::Batch(outputs) => format!("{} recipients", outputs.len()),
+        };
         write!(
             f,
             "Transaction: {} $ZEN  {} ==> {}",
-            self.amount,
+            keys::format_amount(self.total_amount().unwrap_or(u128::MAX)),
             keys::format_address(&self.sender),
-            keys::format_address(&self.recipient)
-        )
+            recipient
+        )?;
+        if self.fee > 0 {
+            write!(f, "  (fee {} $ZEN)", keys::format_amount(self.fee))?;
+        }
+        if let Some(not_before) = self.not_before {
+            write!(f, "  (not before block {})", not_before)?;
+        }
+        if let Some(valid_until) = self.valid_until {
+            write!(f, "  (valid until block {})", valid_until)?;
+        }
+        if let Some(memo) = self.memo_text() {
+            write!(f, "  memo: {:?}", memo)?;
+        }
+        Ok(())
     }
 }
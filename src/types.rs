@@ -1,12 +1,24 @@
 use serde::{Deserialize, Serialize};
 
-use crate::{block::Block, blockchain::AccountState, transaction::Transaction};
+use crate::{
+    block::{Block, BlockHeader},
+    blockchain::{AccountState, AddressInfo, HistoryEntry, TransactionStatus},
+    error::Error,
+    merkle::{AccountProof, InclusionProof},
+    transaction::Transaction,
+};
 
 pub type Address = [u8; 16];
 pub type Hash = [u8; 32];
-pub type TransactionSignature = [u8; 256];
-pub type TransactionData = [u8; 64];
-pub type PublicKey = [u8; 294];
+pub type TransactionSignature = Vec<u8>;
+pub type TransactionData = Vec<u8>;
+/// A key type tag followed by the key bytes, see `keys::encode_public_key`.
+pub type PublicKey = Vec<u8>;
+
+/// Number of decimal places a ZEN can be divided into.
+pub const COIN_DECIMALS: u32 = 8;
+/// Base units per ZEN. All amounts and balances are stored in base units.
+pub const COIN: u128 = 10u128.pow(COIN_DECIMALS);
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum ServerNetworkMessage {
@@ -14,12 +26,77 @@ pub enum ServerNetworkMessage {
     SubmitTransaction(Transaction),
     GetChain,
     BroadcastBlock(Block),
+    /// Miner control, only accepted from the node's own machine.
+    StartMining,
+    StopMining,
+    SetPayoutAddress(Address),
+    GetSupply,
+    GetHistory(Address),
+    /// Pending transactions sent by an address.
+    GetPending(Address),
+    /// Status of the transaction with this id, see `Transaction::id`.
+    GetTransaction(Hash),
+    /// State of an address at the chain tip, with a proof against the tip's state root.
+    GetAccountProof(Address),
+    /// Headers of the main chain, from the first block on.
+    GetHeaders,
+    /// Proof that the confirmed transaction with this id is in its block.
+    GetTransactionProof(Hash),
+    GetChainInfo,
+    GetBlock(BlockId),
+    /// State of an address with its pending and last `recent` confirmed transactions.
+    GetAddressInfo {
+        address: Address,
+        recent: u32,
+    },
+}
+
+impl ServerNetworkMessage {
+    /// Whether the message controls the node's miner rather than querying or relaying.
+    pub fn is_admin(&self) -> bool {
+        matches!(
+            self,
+            ServerNetworkMessage::StartMining
+                | ServerNetworkMessage::StopMining
+                | ServerNetworkMessage::SetPayoutAddress(_)
+        )
+    }
+}
+
+/// A block by hash, or by height on the main chain.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum BlockId {
+    Hash(Hash),
+    Height(u128),
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum ClientNetworkMessage {
     AccountState(AccountState),
     Ack,
-    Error(String),
+    Error(Error),
     Chain(Vec<Block>),
+    Supply {
+        height: u128,
+        issued: u128,
+        max: u128,
+    },
+    History(Vec<HistoryEntry>),
+    Pending(Vec<Transaction>),
+    TransactionStatus(TransactionStatus),
+    /// `None` if there are no blocks yet.
+    AccountProof(Option<Box<AccountProof>>),
+    Headers(Vec<BlockHeader>),
+    /// `None` if the transaction isn't on the main chain.
+    TransactionProof(Option<Box<InclusionProof>>),
+    ChainInfo {
+        height: u128,
+        tip: Hash,
+        difficulty: u32,
+        issued: u128,
+        max: u128,
+    },
+    /// `None` if the node doesn't have the block.
+    Block(Option<Box<Block>>),
+    AddressInfo(AddressInfo),
 }